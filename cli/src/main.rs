@@ -5,7 +5,10 @@ use wikit_core::reader;
 use wikit_core::util;
 use wikit_core::elog;
 use wikit_core::wikit;
+use wikit_core::index;
+use wikit_core::info;
 use wikit_core::preview;
+use wikit_core::config;
 use wikit_core::error::{AnyResult, Context};
 
 use std::path::Path;
@@ -13,45 +16,21 @@ use std::ffi::OsStr;
 use std::fs::File;
 
 use clap::{Arg, App, SubCommand, AppSettings, value_t_or_exit};
-use serde::Deserialize;
 use indoc::indoc;
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
-#[derive(Debug, Deserialize)]
-pub struct MDXMeta {
-    title: String,
-    author: String,
-    description: String,
-}
-
-impl MDXMeta {
-    fn default() -> Self {
-        Self {
-            title: "A generic MDX dictionary".to_string(),
-            author: "An anonymous hero".to_string(),
-            description: "Just for fun".to_string(),
-        }
-    }
-    fn to_string(&self) -> String {
-        let indent = "    ";
-        return format!(
-            "(\n{}title: \"{}\",\n{}author: \"{}\",\n{}description: \"{}\",\n)",
-            indent, &self.title,
-            indent, &self.author,
-            indent, &self.description
-        );
-    }
-}
-
 #[derive(Debug)]
 enum ResourceFormat {
     TEXT,
     WIKIT,
     MDX,
+    MDD,
     POSTGRES,
     MACDICT,
+    MACDDK,
     SQLITE,
+    DIRECTORY,
 }
 
 impl ResourceFormat {
@@ -62,9 +41,12 @@ impl ResourceFormat {
             match Path::new(input).extension().and_then(OsStr::to_str) {
                 Some("txt") | Some("TXT") => Some(ResourceFormat::TEXT),
                 Some("mdx") | Some("MDX") => Some(ResourceFormat::MDX),
+                Some("mdd") | Some("MDD") => Some(ResourceFormat::MDD),
                 Some("wikit") | Some("WIKIT") => Some(ResourceFormat::WIKIT),
                 Some("sqlite") => Some(ResourceFormat::SQLITE),
                 Some("dictionary") => Some(ResourceFormat::MACDICT),
+                Some("ddk") => Some(ResourceFormat::MACDDK),
+                _ if Path::new(input).is_dir() => Some(ResourceFormat::DIRECTORY),
                 _ => None
             }
         }
@@ -78,7 +60,10 @@ async fn main() -> AnyResult<()> {
             tracing_subscriber::fmt::format()
                 .with_file(true)
                 .with_line_number(true)
-        ).init();
+        )
+        // keep stdout clean for output such as `--info --json`
+        .with_writer(std::io::stderr)
+        .init();
 
     let matches = App::new("wikit")
         .setting(AppSettings::ArgRequiredElseHelp)
@@ -102,6 +87,92 @@ async fn main() -> AnyResult<()> {
                 .long("--info")
                 .takes_value(false)
             )
+            .arg(Arg::with_name("json")
+                .help("Dump information as JSON, used with --info")
+                .long("--json")
+                .takes_value(false)
+                .requires("info")
+            )
+            .arg(Arg::with_name("upgrade")
+                .help("Upgrade wikit dictionary to the latest format, it is rewritten in place if <output> is not given")
+                .long("--upgrade")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("verify")
+                .help("Verify integrity of wikit dictionary and report the first broken section")
+                .long("--verify")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("search")
+                .help("Search words whose meaning contains the given text, the wikit dictionary must be created with full-text index")
+                .long("--search")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("glob")
+                .help("Search words matching the glob pattern such as c?t or *tion")
+                .long("--glob")
+                .takes_value(true)
+                .conflicts_with("regex")
+            )
+            .arg(Arg::with_name("regex")
+                .help("Search words matching the regular expression such as c[ao]ts?")
+                .long("--regex")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("limit")
+                .help("The max number of search results")
+                .long("--limit")
+                .takes_value(true)
+                .default_value("20")
+            )
+            .arg(Arg::with_name("duplicate")
+                .help("How to handle words with multiple definitions when creating wikit or macos dictionary: first, last, concat or all, it overrides `duplicate` of the profile")
+                .long("--duplicate")
+                .takes_value(true)
+                .possible_values(&["first", "last", "concat", "all"])
+            )
+            .arg(Arg::with_name("block-size")
+                .help("Maximum size in bytes of key and record block when creating mdx")
+                .long("--block-size")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("compression")
+                .help("Compression of blocks when creating mdx: none, lzo or zlib, old mdict clients may only support none")
+                .long("--compression")
+                .takes_value(true)
+                .possible_values(&["none", "lzo", "zlib"])
+                .default_value("none")
+            )
+            .arg(Arg::with_name("encoding")
+                .help("Text encoding when creating mdx: utf8, utf16 or gb18030, the latter two are for old mdict clients")
+                .long("--encoding")
+                .takes_value(true)
+                .possible_values(&["utf8", "utf16", "gb18030"])
+                .default_value("utf8")
+            )
+            .arg(Arg::with_name("level")
+                .help("Compression level from 0 to 9 when --compression is zlib")
+                .long("--level")
+                .takes_value(true)
+                .default_value("6")
+            )
+            .arg(Arg::with_name("separator")
+                .help("Separator of definitions when --duplicate is concat, <hr> is used by default")
+                .long("--separator")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("regcode")
+                .help("Registration code of encrypted mdx dictionary, it can also be given in wikit.toml")
+                .long("--regcode")
+                .takes_value(true)
+                .requires("userid")
+            )
+            .arg(Arg::with_name("userid")
+                .help("Email or device ID which the registration code of mdx dictionary is issued to")
+                .long("--userid")
+                .takes_value(true)
+                .requires("regcode")
+            )
             .arg(Arg::with_name("output")
                 .help("Same with <input>")
                 .short("-o")
@@ -116,9 +187,11 @@ async fn main() -> AnyResult<()> {
             .arg(Arg::with_name("input")
                 .help(indoc!{"
                     The input file format depends on the value. File suffix reflects the format,
-                    for example .txt => text, .mdx => mdx, .wikit => wikit, .dictionary => macos dictionary, .sqlite =>
-                    sqlite database. If the value is a database url such as postgresql://user@localhost:5432/dictdb,
-                    then the input is a database. Otherwise, the input is treated as a wikit dictionary source directory.
+                    for example .txt => text, .mdx => mdx, .mdd => mdd, .wikit => wikit, .dictionary => macos dictionary,
+                    .ddk => apple dictionary development kit project which can be created on any OS,
+                    .sqlite => sqlite database. If the value is a database url such as postgresql://user@localhost:5432/dictdb,
+                    then the input is a database. Otherwise, the input is treated as a wikit dictionary source directory,
+                    which can also be a directory of resources to be packed into .mdd.
                 "})
                 .required(true)
                 .index(1)
@@ -153,13 +226,81 @@ async fn main() -> AnyResult<()> {
     if let Some(dict) = matches.subcommand_matches("dict") {
         let input = value_t_or_exit!(dict.value_of("input"), String);
         let itype  = ResourceFormat::new(&input).ok_or(elog!("Failed to get input resource format"))?;
+        if let (Some(regcode), Some(userid)) = (dict.value_of("regcode"), dict.value_of("userid")) {
+            config::register_passcode(&input, config::MdxPasscode {
+                regcode: regcode.to_string(),
+                userid: userid.to_string(),
+            });
+        }
         if dict.is_present("info") {
+            let dictinfo = match itype {
+                ResourceFormat::MDX => info::DictInfo::mdx(&input),
+                ResourceFormat::WIKIT => info::DictInfo::wikit(&input),
+                ResourceFormat::SQLITE => info::DictInfo::sqlite(&input),
+                ResourceFormat::DIRECTORY => info::DictInfo::source(&input),
+                _ => {
+                    return Err(elog!("Dump information for {:?} is not supported now", itype));
+                }
+            }.context(elog!("failed to dump information of {}", input))?;
+            if dict.is_present("json") {
+                println!("{}", dictinfo.to_json()?);
+            } else {
+                print!("{}", dictinfo);
+            }
+        } else if dict.is_present("verify") {
             match itype {
-                ResourceFormat::MDX => {
-                    mdict::parse_mdx(&input, Some(mdict::ParseOption::OnlyHeader))?;
+                ResourceFormat::WIKIT => {
+                    // a broken dictionary may fail to load, so it is verified without loading
+                    if let Err(e) = wikit::LocalDictionary::verify_file(&input) {
+                        return Err(elog!("{} is broken: {}", input, e));
+                    }
+                    println!("{} is ok", input);
+                },
+                _ => {
+                    println!("Only wikit dictionary can be verified");
+                }
+            }
+        } else if let Some(query) = dict.value_of("search") {
+            let limit = value_t_or_exit!(dict.value_of("limit"), usize);
+            match itype {
+                ResourceFormat::WIKIT => {
+                    let d = wikit::LocalDictionary::load(&input)
+                        .context(elog!("failed to load wikit dictionary {}", input))?;
+                    for hit in d.search_fulltext(query, limit)? {
+                        println!("{}: {}", hit.word, hit.snippet);
+                    }
+                },
+                _ => {
+                    println!("Only wikit dictionary can be searched");
+                }
+            }
+        } else if dict.is_present("glob") || dict.is_present("regex") {
+            let limit = value_t_or_exit!(dict.value_of("limit"), usize);
+            let (pattern, syntax) = match dict.value_of("glob") {
+                Some(pattern) => (pattern, index::PatternSyntax::Glob),
+                None => (dict.value_of("regex").unwrap_or_default(), index::PatternSyntax::Regex),
+            };
+            match itype {
+                ResourceFormat::WIKIT => {
+                    let d = wikit::LocalDictionary::load(&input)
+                        .context(elog!("failed to load wikit dictionary {}", input))?;
+                    for (word, _) in d.pattern(pattern, syntax, limit)? {
+                        println!("{}", word);
+                    }
                 },
                 _ => {
-                    println!("Dump information for this dictionary type is not supported now")
+                    println!("Only wikit dictionary can be searched");
+                }
+            }
+        } else if dict.is_present("upgrade") {
+            match itype {
+                ResourceFormat::WIKIT => {
+                    let outfile = wikit::LocalDictionary::upgrade(&input, dict.value_of("output"))
+                        .context(elog!("failed to upgrade wikit dictionary"))?;
+                    println!("The upgraded wikit dictionary can be found at {}", outfile.display());
+                },
+                _ => {
+                    println!("Only wikit dictionary can be upgraded");
                 }
             }
         } else {
@@ -172,53 +313,73 @@ async fn main() -> AnyResult<()> {
             };
 
             if dict.is_present("create") {
-                let (pdir, stem, _suffix) = util::parse_path(input.as_str())
-                    .context(elog!("failed to get path of input file: {}", input))?;
+                // policy given by command line overrides the one of the profile
+                let policy = match dict.value_of("duplicate") {
+                    Some(name) => Some(util::DuplicatePolicy::from_name(name, dict.value_of("separator"))
+                        .ok_or(elog!("unknown duplicate policy"))?),
+                    None => None,
+                };
+                if policy.is_some() && !matches!(otype, ResourceFormat::WIKIT | ResourceFormat::MACDICT | ResourceFormat::MACDDK) {
+                    return Err(elog!("--duplicate is only supported when creating wikit or macos dictionary"));
+                }
+                let mut mdx_options = mdict::MdxWriteOptions {
+                    compression: mdict::MdxCompression::from_name(dict.value_of("compression").unwrap_or_default())
+                        .ok_or(elog!("unknown mdx compression"))?,
+                    level: value_t_or_exit!(dict.value_of("level"), u32),
+                    encoding: mdict::MdxEncoding::from_name(dict.value_of("encoding").unwrap_or_default())
+                        .ok_or(elog!("unknown mdx encoding"))?,
+                    ..Default::default()
+                };
+                if dict.is_present("block-size") {
+                    mdx_options.block_size = value_t_or_exit!(dict.value_of("block-size"), usize);
+                }
                 match (itype, otype) {
                     (ResourceFormat::TEXT, ResourceFormat::MDX) => {
-                        // TODO(2021-11-27): read from toml configuration
-                        let title = "wikit dictionary";
-                        let author = "anonymous";
-                        let description = "This dictionary is created by wikit (https://github.com/ikey4u/wikit)";
-                        mdict::create_mdx(title, author, description, &input, &output)?;
+                        let profile = wikit::WikitDictProfile::load(&input)?;
+                        mdict::create_mdx(&profile, &input, &output, &mdx_options)?;
                     },
                     (ResourceFormat::MDX, ResourceFormat::TEXT) => {
-                        let dict = mdict::parse_mdx(input.as_str(), None)?;
-                        mdict::write_into_text(&dict, &output)?;
+                        let reader = mdict::MdxReader::open(&input)?;
+                        mdict::write_into_text(&reader, &output)?;
                     },
                     (ResourceFormat::TEXT, ResourceFormat::MACDICT) => {
+                        let profile = wikit::WikitDictProfile::load(&input)?;
+                        let policy = match policy { Some(policy) => policy, None => profile.duplicate_policy()? };
                         let file = File::open(&input).context(elog!("Cannot open {:?}", &input))?;
-                        let mdxsrc = reader::MDXSource::new(file);
-                        mac::create_mac_dictionary(mdxsrc, input, output, css)
+                        let mdxsrc = reader::MDXSource::new(file).map(Ok);
+                        mac::create_mac_dictionary(mdxsrc, input, output, css, &policy, &profile)
                             .context(elog!("Failed to create mac dictionary"))?;
                     },
                     (ResourceFormat::MDX, ResourceFormat::MACDICT) => {
-                        let textpath = pdir.join(stem + "_wikit.txt");
-                        if !textpath.exists() {
-                            let dict = mdict::parse_mdx(input.as_str(), None)?;
-                            if mdict::write_into_text(&dict, textpath.as_path()).is_err() {
-                                std::fs::remove_file(textpath.as_path())
-                                    .context(elog!("cannot remove file {}", textpath.display()))?;
-                            };
-                        }
-                        println!("[+] Open mdx soure file ...");
-                        let file = File::open(&textpath)
-                            .context(elog!("Cannot open {:?}", textpath.display()))?;
-                        let mdxsrc = reader::MDXSource::new(file);
+                        let profile = wikit::WikitDictProfile::load(&input)?;
+                        let policy = match policy { Some(policy) => policy, None => profile.duplicate_policy()? };
+                        let reader = mdict::MdxReader::open(&input)?;
                         println!("[+] Create mac dictionary ...");
-                        mac::create_mac_dictionary(mdxsrc, input, output, css)
+                        mac::create_mac_dictionary(reader.entries(), &input, &output, css.as_ref(), &policy, &profile)
                             .context(elog!("Failed to create mac dictionary"))?;
                         println!("[+] Create mac dictionary is done");
-                        if textpath.exists() {
-                            std::fs::remove_file(textpath.as_path()).context(
-                                elog!("cannot remove file {}, you may remove it manually", textpath.display())
-                            )?;
-                        }
+                    },
+                    (ResourceFormat::TEXT, ResourceFormat::MACDDK) => {
+                        let profile = wikit::WikitDictProfile::load(&input)?;
+                        let policy = match policy { Some(policy) => policy, None => profile.duplicate_policy()? };
+                        let file = File::open(&input).context(elog!("Cannot open {:?}", &input))?;
+                        let mdxsrc = reader::MDXSource::new(file).map(Ok);
+                        let project = mac::create_mac_project(mdxsrc, &input, &output, css.as_ref(), &policy, &profile)
+                            .context(elog!("Failed to create mac dictionary project"))?;
+                        println!("The mac dictionary project can be found at {}, run make in it on macOS to build {}.dictionary", project.dir.display(), project.name);
+                    },
+                    (ResourceFormat::MDX, ResourceFormat::MACDDK) => {
+                        let profile = wikit::WikitDictProfile::load(&input)?;
+                        let policy = match policy { Some(policy) => policy, None => profile.duplicate_policy()? };
+                        let reader = mdict::MdxReader::open(&input)?;
+                        let project = mac::create_mac_project(reader.entries(), &input, &output, css.as_ref(), &policy, &profile)
+                            .context(elog!("Failed to create mac dictionary project"))?;
+                        println!("The mac dictionary project can be found at {}, run make in it on macOS to build {}.dictionary", project.dir.display(), project.name);
                     },
                     (ResourceFormat::MDX, ResourceFormat::POSTGRES) => {
                         let table = dict.value_of("table").expect("Please specify database table name");
-                        let pairs = mdict::parse_mdx(input.as_str(), None)?;
-                        mdict::save_into_db(pairs.entries, &output, table).await?;
+                        let reader = mdict::MdxReader::open(&input)?;
+                        mdict::save_into_db(reader.entries(), &output, table).await?;
                     }
                     // convert `.wikit.txt` source file into sqlite database
                     (ResourceFormat::TEXT, ResourceFormat::SQLITE) => {
@@ -249,10 +410,29 @@ async fn main() -> AnyResult<()> {
                     }
                     (ResourceFormat::MDX | ResourceFormat::TEXT, ResourceFormat::WIKIT) => {
                         let outfile = Path::new(&output);
-                        let outfile = wikit::LocalDictionary::create(&input, Some(outfile))
+                        let outfile = wikit::LocalDictionary::create_with_policy(&input, Some(outfile), policy.as_ref())
                             .context(elog!("failed to create wikit dictionary"))?;
                         println!("The wikit dictionary can be found at {}", outfile.display());
                     },
+                    (ResourceFormat::WIKIT, ResourceFormat::TEXT) => {
+                        let wikit_dict = wikit::LocalDictionary::load(&input)
+                            .context(elog!("failed to load {}", input))?;
+                        if output.ends_with(".wikit.txt") {
+                            wikit_dict.write_into_wikit_source(&output)?;
+                        } else {
+                            wikit_dict.write_into_text(&output)?;
+                        }
+                    },
+                    // pack resources in directory into mdd which can be used alongside with mdx
+                    (ResourceFormat::DIRECTORY, ResourceFormat::MDD) => {
+                        mdict::create_mdd(&input, &output)?;
+                        println!("The mdd file can be found at {}", output);
+                    },
+                    (ResourceFormat::WIKIT, ResourceFormat::MDX) => {
+                        let wikit_dict = wikit::LocalDictionary::load(&input)
+                            .context(elog!("failed to load {}", input))?;
+                        wikit_dict.write_into_mdx(&output, &mdx_options)?;
+                    },
                     (i, o) => {
                         return Err(elog!("Does not support creating {:?} from {:?} for now", o, i));
                     },
@@ -8,23 +8,48 @@ use once_cell::sync::Lazy;
 #[derive(Debug)]
 pub enum CacheValue {
     StringList(Vec<String>),
-    WikitDictionary(wikit::WikitDictionary),
+    WikitDictionary(Arc<wikit::WikitDictionary>),
 }
 
 pub static CACHE: Lazy<Arc<Mutex<HashMap<String, CacheValue>>>> = Lazy::new(|| {
     Arc::new(Mutex::new(HashMap::new()))
 });
 
+/// Get dictionary of `uri` such as `file:///path/to/dict.mdx`, it is loaded by
+/// [wikit::load_dictionary_from_uri] on first use and shared by the later calls
+///
+/// The cache lock is not held while a dictionary is loading, so a slow load does not block
+/// lookups of other dictionaries. A dictionary failed to load is not cached and is tried again
+/// on next use.
+pub fn load_dictionary<S>(uri: S) -> Option<Arc<wikit::WikitDictionary>> where S: AsRef<str> {
+    let dictkey = format!("server.dict://{}", uri.as_ref());
+    if let Ok(c) = CACHE.lock() {
+        if let Some(CacheValue::WikitDictionary(dict)) = c.get(dictkey.as_str()) {
+            return Some(dict.clone());
+        }
+    }
+
+    let dict = Arc::new(wikit::load_dictionary_from_uri(uri.as_ref())?);
+    let mut c = CACHE.lock().ok()?;
+    // keep the first one if the dictionary is loaded by concurrent calls
+    match c.entry(dictkey).or_insert(CacheValue::WikitDictionary(dict.clone())) {
+        CacheValue::WikitDictionary(cached) => Some(cached.clone()),
+        _ => Some(dict),
+    }
+}
+
 fn query(word: String, dictname: String) -> String {
     let dictkey = format!("server.dict://{}", dictname);
 
     if let Ok(c) = CACHE.lock() {
         if let Some(CacheValue::WikitDictionary(dict)) = c.get(dictkey.as_str()) {
-            match dict {
+            match dict.as_ref() {
                 wikit::WikitDictionary::Local(d) => {
                 },
                 wikit::WikitDictionary::Remote(d) => {
                 },
+                wikit::WikitDictionary::Mdx(d) => {
+                },
             }
         } else {
         }
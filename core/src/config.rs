@@ -15,8 +15,15 @@
 ///     port = 8888
 ///     host = "0.0.0.0"
 ///
+///     [passcodes."/home/user/Downloads/awesome.mdx"]
+///     regcode = "<registration code in hex>"
+///     userid = "<email or device ID>"
+///
 /// `[cltcfg]` is used for wikit desktop client, and `[srvcfg]` is used for serving dictionaries.
 ///
+/// `[passcodes]` contains registration of encrypted mdx dictionaries, the key is the path or the
+/// file name of mdx file, and the user ID is treated as email if it contains `@`.
+///
 /// `uris` are a list of [URI](https://en.wikipedia.org/wiki/Uniform_Resource_Identifier) which
 /// refers directory resource path, supported URIs are
 ///
@@ -55,8 +62,10 @@ use crate::error::{AnyResult, Context};
 use crate::elog;
 
 use std::fs::{self, File};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::io::{Read, Write};
+use std::collections::HashMap;
+use std::sync::Mutex;
 
 use dirs;
 use once_cell::sync::Lazy;
@@ -99,12 +108,28 @@ impl Default for ServerConfig {
     }
 }
 
+/// Registration of encrypted mdx dictionary
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MdxPasscode {
+    /// Registration code in hex
+    pub regcode: String,
+    /// Email or device ID which the registration code is issued to
+    pub userid: String,
+}
+
+// passcodes given at runtime such as by command line, which take precedence over configuration
+static PASSCODES: Lazy<Mutex<HashMap<PathBuf, MdxPasscode>>> = Lazy::new(|| {
+    Mutex::new(HashMap::new())
+});
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct WikitConfig {
     #[serde(default)]
     pub cltcfg: ClientConfig,
     #[serde(default)]
     pub srvcfg: ServerConfig,
+    #[serde(default)]
+    pub passcodes: HashMap<String, MdxPasscode>,
 }
 
 impl Default for WikitConfig {
@@ -112,10 +137,34 @@ impl Default for WikitConfig {
         WikitConfig {
             cltcfg: ClientConfig::default(),
             srvcfg: ServerConfig::default(),
+            passcodes: HashMap::new(),
         }
     }
 }
 
+/// Use `passcode` for mdx dictionary `path` in this process
+pub fn register_passcode<P: AsRef<Path>>(path: P, passcode: MdxPasscode) {
+    let path = path.as_ref();
+    let path = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    if let Ok(mut passcodes) = PASSCODES.lock() {
+        passcodes.insert(path, passcode);
+    }
+}
+
+/// Find passcode of mdx dictionary `path` from registered passcodes and then configuration
+pub fn find_passcode<P: AsRef<Path>>(path: P) -> Option<MdxPasscode> {
+    let path = path.as_ref();
+    let path = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    if let Some(passcode) = PASSCODES.lock().ok()?.get(&path) {
+        return Some(passcode.clone());
+    }
+    let name = path.file_name()?.to_str()?;
+    load_config().ok()?.passcodes.into_iter().find_map(|(k, v)| {
+        let matched = k == name || fs::canonicalize(&k).map(|k| k == path).unwrap_or(false);
+        matched.then(|| v)
+    })
+}
+
 pub fn load_config() -> AnyResult<WikitConfig> {
     let confdir = get_config_dir().context(elog!("cannot get user config directory"))?;
     let confpath = confdir.join("wikit.toml");
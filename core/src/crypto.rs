@@ -7,3 +7,59 @@ pub fn md5(data: &[u8]) -> String {
     let r = hasher.finalize();
     return hex::encode(r);
 }
+
+/// Encrypt or decrypt `data` by Salsa20/8 with 128-bit `key` and zero nonce, which is used by
+/// mdx to protect key blocks with registration code
+pub fn salsa20_8(key: &[u8; 16], data: &[u8]) -> Vec<u8> {
+    salsa20(key, 8, data)
+}
+
+fn salsa20(key: &[u8; 16], rounds: usize, data: &[u8]) -> Vec<u8> {
+    let word = |b: &[u8], i: usize| u32::from_le_bytes([b[i * 4], b[i * 4 + 1], b[i * 4 + 2], b[i * 4 + 3]]);
+    // 128-bit key is used twice with constants "expand 16-byte k", and the nonce is zero
+    let tau = b"expand 16-byte k";
+    let mut out = Vec::with_capacity(data.len());
+    for (counter, chunk) in data.chunks(64).enumerate() {
+        let counter = counter as u64;
+        let input: [u32; 16] = [
+            word(tau, 0), word(key, 0), word(key, 1), word(key, 2),
+            word(key, 3), word(tau, 1), 0, 0,
+            counter as u32, (counter >> 32) as u32, word(tau, 2), word(key, 0),
+            word(key, 1), word(key, 2), word(key, 3), word(tau, 3),
+        ];
+        let mut x = input;
+        let quarter = |x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize| {
+            x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+            x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+            x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+            x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+        };
+        for _ in 0..rounds / 2 {
+            // column round
+            quarter(&mut x, 0, 4, 8, 12);
+            quarter(&mut x, 5, 9, 13, 1);
+            quarter(&mut x, 10, 14, 2, 6);
+            quarter(&mut x, 15, 3, 7, 11);
+            // row round
+            quarter(&mut x, 0, 1, 2, 3);
+            quarter(&mut x, 5, 6, 7, 4);
+            quarter(&mut x, 10, 11, 8, 9);
+            quarter(&mut x, 15, 12, 13, 14);
+        }
+        let stream = x.iter().zip(input.iter())
+            .flat_map(|(x, i)| x.wrapping_add(*i).to_le_bytes())
+            .collect::<Vec<u8>>();
+        out.extend(chunk.iter().zip(stream.iter()).map(|(c, s)| c ^ s));
+    }
+    out
+}
+
+#[test]
+fn test_salsa20() {
+    // test vector of set 1 vector 0 from eSTREAM
+    let mut key = [0u8; 16];
+    key[0] = 0x80;
+    let stream = salsa20(&key, 20, &[0u8; 64]);
+    assert_eq!(hex::encode(&stream[..16]), "4dfa5e481da23ea09a31022050859936");
+    assert_eq!(salsa20_8(&key, &salsa20_8(&key, b"wikit")), b"wikit");
+}
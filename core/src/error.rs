@@ -43,6 +43,9 @@ pub enum WikitError {
 
     #[error("{0}")]
     SQLiteError(#[from] rusqlite::Error),
+
+    #[error("{section} section is broken at offset {offset:#x}")]
+    Corrupted { section: String, offset: u64 },
 }
 
 // TODO(2022-04-04): refactor out WikitResult<T> and use Result<T>
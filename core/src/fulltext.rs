@@ -0,0 +1,222 @@
+/// This module is used to build and search full-text index of word meanings
+
+use crate::error::{WikitResult, WikitError};
+use crate::index;
+
+use std::collections::BTreeMap;
+use std::io::SeekFrom;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// tokens longer than this (in bytes) are not indexed
+const MAX_TOKEN_SIZE: usize = 64;
+// the number of characters kept before and after the matched text in snippet
+const SNIPPET_CONTEXT: usize = 40;
+
+static RE_SCRIPT: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<script[^>]*>.*?</script>").unwrap());
+static RE_STYLE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<style[^>]*>.*?</style>").unwrap());
+static RE_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<[^>]*>").unwrap());
+static RE_SPACE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\s+").unwrap());
+
+/// A headword whose meaning matches the full-text query
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FulltextHit {
+    pub word: String,
+    // plain text around the matched text
+    pub snippet: String,
+}
+
+/// Get plain text of html `meaning` with scripts, styles and tags removed
+pub fn strip_html<S: AsRef<str>>(meaning: S) -> String {
+    let text = RE_SCRIPT.replace_all(meaning.as_ref(), " ");
+    let text = RE_STYLE.replace_all(&text, " ");
+    let text = RE_TAG.replace_all(&text, " ");
+    let text = text.trim_matches(char::from(0))
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    RE_SPACE.replace_all(text.trim(), " ").to_string()
+}
+
+/// Split plain text into lowercase tokens
+///
+/// A token is either a run of letters and digits, or a single CJK character since words of CJK
+/// languages are not separated by spaces.
+pub fn tokenize<S: AsRef<str>>(text: S) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    for c in text.as_ref().chars() {
+        if is_cjk(c) || !c.is_alphanumeric() {
+            if token.len() > 0 {
+                tokens.push(std::mem::take(&mut token));
+            }
+            if is_cjk(c) {
+                tokens.push(c.to_string());
+            }
+        } else {
+            token.extend(c.to_lowercase());
+        }
+    }
+    if token.len() > 0 {
+        tokens.push(token);
+    }
+    tokens.retain(|x| x.len() <= MAX_TOKEN_SIZE);
+    tokens
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}' | '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' |
+        '\u{f900}'..='\u{faff}' | '\u{ac00}'..='\u{d7af}'
+    )
+}
+
+/// Write full-text postings and index for `words` which is a list of `(word, meaning, value)`
+/// sorted by word, where `value` is the index value of meaning.
+///
+/// The postings section starts with a word table, each item is
+///
+///      wordsz:2
+///      word:wordsz
+///      value:8
+///
+/// and followed by postings lists, each list is
+///
+///      count:4
+///      wordref:4 (repeated count times)
+///
+/// where `wordref` is the offset of item in word table from the start of postings section. The
+/// returned index maps every token to the offset of its postings list from the start of postings
+/// section. The `(postings base, postings size, index base, index size)` is returned.
+pub fn write<S, W>(words: &[(S, S, u64)], writer: &mut W) -> WikitResult<(u64, u64, u64, u64)>
+where
+    S: AsRef<str>,
+    W: std::io::Write + std::io::Seek,
+{
+    let pbase = writer.seek(SeekFrom::Current(0))?;
+    let mut postings: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    let mut wordref = 0u32;
+    for (word, meaning, value) in words.iter() {
+        let word = word.as_ref().as_bytes();
+        writer.write_all(&(word.len() as u16).to_be_bytes()[..])?;
+        writer.write_all(word)?;
+        writer.write_all(&value.to_be_bytes()[..])?;
+        for token in tokenize(strip_html(meaning)) {
+            let list = postings.entry(token).or_default();
+            if list.last() != Some(&wordref) {
+                list.push(wordref);
+            }
+        }
+        wordref = wordref.checked_add(2 + word.len() as u32 + 8)
+            .ok_or(WikitError::new("too many words for full-text index"))?;
+    }
+
+    let mut offset = wordref as u64;
+    let mut token_table = vec![];
+    for (token, list) in postings.iter() {
+        writer.write_all(&(list.len() as u32).to_be_bytes()[..])?;
+        for wordref in list {
+            writer.write_all(&wordref.to_be_bytes()[..])?;
+        }
+        token_table.push((token, offset));
+        offset += 4 + 4 * list.len() as u64;
+    }
+    let psz = writer.seek(SeekFrom::Current(0))? - pbase;
+
+    let (fibase, fisz) = index::FSTIndex::write(&mut token_table.iter(), writer)?;
+    Ok((pbase, psz, fibase, fisz))
+}
+
+/// Reader of the postings section written by [write]
+pub struct Postings<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Postings<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    /// Get word references of postings list at `offset`
+    pub fn list(&self, offset: u64) -> WikitResult<Vec<u32>> {
+        let count = self.read_u32(offset)? as u64;
+        (0..count).map(|i| self.read_u32(offset + 4 + 4 * i)).collect()
+    }
+
+    /// Get `(word, value)` of word table item referred by `wordref`
+    pub fn word(&self, wordref: u32) -> WikitResult<(String, u64)> {
+        let offset = wordref as usize;
+        let wordsz = u16::from_be_bytes(self.slice(offset, 2)?.try_into().unwrap()) as usize;
+        let word = String::from_utf8(self.slice(offset + 2, wordsz)?.to_vec())?;
+        let value = u64::from_be_bytes(self.slice(offset + 2 + wordsz, 8)?.try_into().unwrap());
+        Ok((word, value))
+    }
+
+    fn read_u32(&self, offset: u64) -> WikitResult<u32> {
+        Ok(u32::from_be_bytes(self.slice(offset as usize, 4)?.try_into().unwrap()))
+    }
+
+    fn slice(&self, offset: usize, len: usize) -> WikitResult<&'a [u8]> {
+        self.buf.get(offset..offset + len)
+            .ok_or(WikitError::new(format!("full-text postings is broken at {:#x}", offset)))
+    }
+}
+
+/// Intersect sorted lists
+pub fn intersect(mut lists: Vec<Vec<u32>>) -> Vec<u32> {
+    lists.sort_by_key(|x| x.len());
+    let mut lists = lists.into_iter();
+    let mut r = lists.next().unwrap_or_default();
+    for list in lists {
+        r.retain(|x| list.binary_search(x).is_ok());
+    }
+    r
+}
+
+/// Get snippet of plain `text` around the first occurrence of `query` or its first token,
+/// and whether `query` occurs in `text` as a whole.
+pub fn snippet<S: AsRef<str>>(text: S, query: S) -> (String, bool) {
+    let text = text.as_ref();
+    let lower = text.to_lowercase();
+    let query = query.as_ref().trim().to_lowercase();
+    // lowercasing may change byte length of text, so the lowercase text is searched only if the
+    // length is unchanged
+    let find = |needle: &str| {
+        if lower.len() == text.len() {
+            lower.find(needle)
+        } else {
+            text.find(needle)
+        }
+    };
+    let (pos, phrase) = match find(&query) {
+        Some(pos) => (Some(pos), true),
+        None => (tokenize(&query).first().and_then(|x| find(x)), false),
+    };
+    let pos = pos.filter(|x| text.is_char_boundary(*x)).unwrap_or(0);
+
+    let start = text[..pos].char_indices().rev().take(SNIPPET_CONTEXT).last().map(|x| x.0).unwrap_or(pos);
+    let before = text[start..pos].chars().count();
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push_str("...");
+    }
+    let mut rest = text[start..].chars();
+    snippet.extend(rest.by_ref().take(before + query.chars().count() + SNIPPET_CONTEXT));
+    if rest.next().is_some() {
+        snippet.push_str("...");
+    }
+    (snippet, phrase)
+}
+
+#[test]
+fn test_tokenize() {
+    assert_eq!(strip_html("<b>Hello</b>,&nbsp;<i>World</i>!<style>b {}</style>"), "Hello , World !");
+    assert_eq!(tokenize("Hello, World! 你好x2"), vec!["hello", "world", "你", "好", "x2"]);
+    let (s, phrase) = snippet("the quick brown fox", "brown");
+    assert_eq!((s.as_str(), phrase), ("the quick brown fox", true));
+}
@@ -1,15 +1,25 @@
 /// This module is used to build index for dictionary
 
-use crate::error::{WikitResult};
+use crate::error::{WikitResult, WikitError};
+use crate::util;
 
 use std::io::{SeekFrom};
-use std::fs::File;
+use std::sync::Arc;
 
-use fst::automaton::Levenshtein;
+use fst::automaton::{Automaton, Levenshtein, Str};
 use fst::{IntoStreamer, Streamer, Map, MapBuilder};
-use memmap::MmapOptions;
+use memmap::{Mmap, MmapMut};
+use regex_automata::dense;
 use serde::{Deserialize, Serialize};
 
+/// The max edit distance of fuzzy lookup, larger distance is clamped to it since the automaton
+/// grows quickly with distance
+pub const MAX_LOOKUP_DISTANCE: u32 = 2;
+
+// the max memory of DFA built from headword pattern, states of DFA are identified by u16 so that
+// determinization of pattern such as `.{100}` stops at 65535 states too
+const PATTERN_DFA_SIZE_LIMIT: usize = 8 * 1024 * 1024;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum IndexFormat {
@@ -25,11 +35,135 @@ impl IndexFormat {
     }
 }
 
+/// Region of a memory map shared by all readers of a dictionary file
+#[derive(Clone)]
+pub struct MmapSlice {
+    mmap: Arc<Mmap>,
+    offset: usize,
+    length: usize,
+}
+
+impl MmapSlice {
+    pub fn new(mmap: Arc<Mmap>, offset: u64, length: u64) -> Option<Self> {
+        let end = offset.checked_add(length)?;
+        if end > mmap.len() as u64 {
+            return None;
+        }
+        Some(Self { mmap, offset: offset as usize, length: length as usize })
+    }
+}
+
+impl AsRef<[u8]> for MmapSlice {
+    fn as_ref(&self) -> &[u8] {
+        &self.mmap[self.offset..self.offset + self.length]
+    }
+}
+
+/// How keywords are normalized before they are indexed and queried
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum KeyNormalization {
+    /// Keywords are kept as they are, such as resource paths
+    None = 0,
+    /// Keywords are trimmed and lowercased, which is used before wikit format version 0x05
+    Lowercase = 1,
+    /// Keywords are normalized by NFKC, trimmed and lowercased
+    Nfkc = 2,
+    /// Same with `Nfkc` but accents are folded too
+    NfkcFold = 3,
+}
+
+impl KeyNormalization {
+    pub fn new(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(KeyNormalization::None),
+            1 => Some(KeyNormalization::Lowercase),
+            2 => Some(KeyNormalization::Nfkc),
+            3 => Some(KeyNormalization::NfkcFold),
+            _ => None,
+        }
+    }
+
+    pub fn normalize<S: AsRef<str>>(&self, keyword: S) -> String {
+        match self {
+            KeyNormalization::None => keyword.as_ref().to_string(),
+            KeyNormalization::Lowercase => util::normalize_word(keyword),
+            KeyNormalization::Nfkc => util::normalize_key(keyword, false),
+            KeyNormalization::NfkcFold => util::normalize_key(keyword, true),
+        }
+    }
+}
+
+/// Options of fuzzy lookup
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LookupOptions {
+    /// Max edit distance, it is chosen by the length of keyword if it is none, and it is clamped
+    /// to [MAX_LOOKUP_DISTANCE]
+    pub max_distance: Option<u32>,
+    /// Max number of results
+    pub limit: usize,
+    /// Whether to include the keyword itself if it is found
+    pub exact: bool,
+    /// Whether to count transposition of two adjacent characters as one edit
+    pub transpose: bool,
+}
+
+impl LookupOptions {
+    /// Max edit distance clamped to [MAX_LOOKUP_DISTANCE]
+    pub fn distance(&self) -> Option<u32> {
+        self.max_distance.map(|x| x.min(MAX_LOOKUP_DISTANCE))
+    }
+}
+
+impl Default for LookupOptions {
+    fn default() -> Self {
+        Self {
+            max_distance: None,
+            limit: 20,
+            exact: true,
+            transpose: false,
+        }
+    }
+}
+
+/// Syntax of headword pattern
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PatternSyntax {
+    /// `?` matches any character, `*` matches any characters and `[...]` matches one of the
+    /// characters in brackets (or not in brackets if it starts with `!`)
+    Glob,
+    /// Regular expression supported by `regex` crate, it must match the whole headword which is
+    /// normalized as index key, so literal letters are normalized too such as `Café.*` is
+    /// matched as `cafe.*` if accents are folded, but escaped letters such as `\W` are kept
+    Regex,
+}
+
+impl PatternSyntax {
+    pub fn from_name<S: AsRef<str>>(name: S) -> Option<Self> {
+        match name.as_ref().to_lowercase().as_str() {
+            "glob" => Some(PatternSyntax::Glob),
+            "regex" => Some(PatternSyntax::Regex),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct FSTIndex {
-    path: std::path::PathBuf,
-    offset: u64,
-    length: u64,
+    map: Map<MmapSlice>,
+    norm: KeyNormalization,
+}
+
+impl std::fmt::Debug for FSTIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let data = self.map.as_fst().as_inner();
+        f.debug_struct("FSTIndex")
+            .field("offset", &data.offset)
+            .field("length", &data.length)
+            .field("len", &self.map.len())
+            .field("norm", &self.norm)
+            .finish()
+    }
 }
 
 impl FSTIndex {
@@ -54,40 +188,308 @@ impl FSTIndex {
         IndexFormat::FST
     }
 
-    pub fn lookup<P>(&self, keyword: P) -> WikitResult<Vec<(String, u64)>> where P: AsRef<str> {
-        let file = File::open(&self.path)?;
-        let mmap = unsafe { MmapOptions::new().offset(self.offset).len(self.length as usize).map(&file)? };
-        let map = Map::new(mmap)?;
+    /// Number of keywords
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
 
-        let fuzzycnt = match keyword.as_ref().len() {
-            0 | 1 | 2 => 0,
-            3 | 4 | 5 => 1,
-            _ => 2,
+    /// Search keywords similar to `keyword` and return `(keyword, offset)` pairs ranked by edit
+    /// distance and then by keyword, see [LookupOptions] for the available options.
+    pub fn lookup<P>(&self, keyword: P, options: &LookupOptions) -> WikitResult<Vec<(String, u64)>> where P: AsRef<str> {
+        let keyword = self.norm.normalize(keyword);
+        let keyword = keyword.as_str();
+        let distance = options.distance().unwrap_or_else(|| {
+            match keyword.chars().count() {
+                0 | 1 | 2 => 0,
+                3 | 4 | 5 => 1,
+                _ => 2,
+            }
+        });
+        // a transposition costs two edits in levenshtein distance, one more edit is allowed for
+        // a transposition since doubling the distance makes the automaton too large for long
+        // keyword, and it falls back to `distance` if it is still too large
+        let query = if options.transpose {
+            Levenshtein::new(keyword, distance.saturating_add(1))
+                .or_else(|_| Levenshtein::new(keyword, distance))?
+        } else {
+            Levenshtein::new(keyword, distance)?
         };
-        let query = Levenshtein::new(keyword.as_ref(), fuzzycnt)?;
-        let mut stream = map.search(&query).into_stream();
 
         let mut r = vec![];
-        if let Some(v) = map.get(keyword.as_ref()) {
-            r.push((keyword.as_ref().to_string(), v));
+        let mut stream = self.map.search(&query).into_stream();
+        while let Some((k, v)) = stream.next() {
+            let k = String::from_utf8(k.to_vec())?;
+            if !options.exact && k == keyword {
+                continue;
+            }
+            let d = edit_distance(keyword, &k, options.transpose);
+            if d <= distance {
+                r.push((d, k, v));
+            }
         }
+        r.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        Ok(r.into_iter().take(options.limit).map(|(_, k, v)| (k, v)).collect())
+    }
 
-        let (mut cnt, limit) = (0, 20);
-        while let Some((k, v)) = stream.next() {
-            r.push((String::from_utf8(k.to_vec())?, v));
-            cnt += 1;
-            if cnt >= limit {
-                break;
+    /// Get at most `limit` `(keyword, offset)` pairs whose keyword starts with `prefix` in
+    /// lexicographical order
+    pub fn prefix<P>(&self, prefix: P, limit: usize) -> WikitResult<Vec<(String, u64)>> where P: AsRef<str> {
+        let prefix = self.norm.normalize(prefix);
+        let query = Str::new(&prefix).starts_with();
+        let mut stream = self.map.search(query).into_stream();
+        let mut r = vec![];
+        while r.len() < limit {
+            match stream.next() {
+                Some((k, v)) => r.push((String::from_utf8(k.to_vec())?, v)),
+                None => break,
             }
         }
         Ok(r)
     }
 
-    pub fn new(path: std::path::PathBuf, offset: u64, length: u64) -> Self {
-        Self {
-            path,
-            offset,
-            length,
+    /// Get at most `limit` `(keyword, offset)` pairs whose keyword matches `pattern` of `syntax`
+    /// in lexicographical order
+    ///
+    /// Literal characters of `pattern` are normalized as keywords, and an error is returned if
+    /// the pattern is too complex to search.
+    pub fn pattern<P>(&self, pattern: P, syntax: PatternSyntax, limit: usize) -> WikitResult<Vec<(String, u64)>> where P: AsRef<str> {
+        let pattern = match syntax {
+            PatternSyntax::Glob => glob_to_regex(&self.norm.normalize(pattern)),
+            PatternSyntax::Regex => normalize_regex_literals(pattern.as_ref(), self.norm),
+        };
+        let dfa = dense::Builder::new().anchored(true).premultiply(false).build_with_size::<u16>(&pattern)
+            .map_err(|e| match e.kind() {
+                regex_automata::ErrorKind::StateIDOverflow { .. } => WikitError::new(format!("pattern {} is too complex", pattern)),
+                _ => WikitError::new(format!("invalid pattern {}: {}", pattern, e)),
+            })?;
+        if dfa.memory_usage() > PATTERN_DFA_SIZE_LIMIT {
+            return Err(WikitError::new(format!("pattern {} is too complex", pattern)));
+        }
+        let mut stream = self.map.search(&dfa).into_stream();
+        let mut r = vec![];
+        while r.len() < limit {
+            match stream.next() {
+                Some((k, v)) => r.push((String::from_utf8(k.to_vec())?, v)),
+                None => break,
+            }
         }
+        Ok(r)
+    }
+
+    /// Get the offset of `keyword` with exact match
+    pub fn get<P>(&self, keyword: P) -> WikitResult<Option<u64>> where P: AsRef<str> {
+        Ok(self.map.get(self.norm.normalize(keyword)))
+    }
+
+    /// Check the checksum of index
+    pub fn verify(&self) -> WikitResult<()> {
+        self.map.as_fst().verify()?;
+        Ok(())
+    }
+
+    /// Iterate all `(keyword, offset)` pairs in lexicographical order
+    pub fn iter(&self) -> WikitResult<FSTKeys<'_>> {
+        Ok(FSTKeys { stream: self.map.stream() })
+    }
+
+    /// Load index from `length` bytes at `offset` of the memory map, whose keywords are
+    /// normalized by `norm`
+    pub fn new(mmap: Arc<Mmap>, offset: u64, length: u64, norm: KeyNormalization) -> WikitResult<Self> {
+        let data = MmapSlice::new(mmap, offset, length)
+            .ok_or(WikitError::new("index is out of the dictionary file"))?;
+        Ok(Self { map: Map::new(data)?, norm })
+    }
+
+    /// Build index in memory from `(keyword, offset)` pairs sorted by keyword, which are
+    /// normalized by `norm` already
+    pub fn build<S>(iter: &mut dyn Iterator<Item = &(S, u64)>, norm: KeyNormalization) -> WikitResult<Self> where S: AsRef<str> {
+        let mut buf = std::io::Cursor::new(vec![]);
+        let (_, length) = Self::write(iter, &mut buf)?;
+        let buf = buf.into_inner();
+        let mut mmap = MmapMut::map_anon(buf.len())?;
+        mmap.copy_from_slice(&buf[..]);
+        Self::new(Arc::new(mmap.make_read_only()?), 0, length, norm)
     }
 }
+
+/// Iterator over `(keyword, offset)` pairs of [FSTIndex] in lexicographical order
+pub struct FSTKeys<'a> {
+    stream: fst::map::Stream<'a>,
+}
+
+impl<'a> Iterator for FSTKeys<'a> {
+    type Item = WikitResult<(String, u64)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.stream.next()?;
+        Some(String::from_utf8(k.to_vec()).map(|k| (k, v)).map_err(WikitError::from))
+    }
+}
+
+// Translate glob pattern into regular expression
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                let mut class = String::new();
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    class.push('^');
+                }
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        closed = true;
+                        break;
+                    }
+                    if c == '\\' || c == '[' || c == '&' || c == '~' {
+                        class.push('\\');
+                    }
+                    class.push(c);
+                }
+                if closed && class.len() > 0 && class != "^" {
+                    regex.push_str(&format!("[{}]", class));
+                } else {
+                    // an unclosed or empty bracket is a literal
+                    regex.push_str(&regex::escape(&format!("[{}", class)));
+                    if closed {
+                        regex.push_str(&regex::escape("]"));
+                    }
+                }
+            },
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex
+}
+
+// Normalize literal letters of regular expression `pattern` by `norm`, escapes such as `\W`,
+// `\pL` and `\p{Greek}`, flags such as `(?U)` and group names such as `(?P<Name>...)` are kept
+fn normalize_regex_literals(pattern: &str, norm: KeyNormalization) -> String {
+    let mut regex = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        regex.push(c);
+        match c {
+            '\\' => {
+                let c = match chars.next() {
+                    Some(c) => c,
+                    None => break,
+                };
+                regex.push(c);
+                // argument of escape is one letter or hex digits such as `\pL` and `\x41`, or
+                // it is in braces such as `\p{Greek}`
+                let argsz = match c {
+                    'p' | 'P' => 1,
+                    'x' => 2,
+                    'u' => 4,
+                    'U' => 8,
+                    _ => 0,
+                };
+                if argsz > 0 && chars.peek() == Some(&'{') {
+                    for c in chars.by_ref() {
+                        regex.push(c);
+                        if c == '}' {
+                            break;
+                        }
+                    }
+                } else {
+                    regex.extend(chars.by_ref().take(argsz));
+                }
+            },
+            '(' if chars.peek() == Some(&'?') => {
+                regex.extend(chars.next());
+                // group name such as `(?P<name>` or flags such as `(?i)` and `(?U:`
+                let end = if matches!(chars.peek(), Some('P') | Some('<')) { '>' } else { ':' };
+                while let Some(c) = chars.next_if(|c| *c != ')') {
+                    regex.push(c);
+                    if c == end {
+                        break;
+                    }
+                }
+            },
+            _ if c.is_alphabetic() => {
+                let normalized = norm.normalize(c.to_string());
+                // keep it if it is normalized into multiple characters which may change the
+                // meaning of regular expression such as `ǆ+`
+                if normalized.chars().count() == 1 {
+                    regex.pop();
+                    regex.push_str(&normalized);
+                }
+            },
+            _ => {},
+        }
+    }
+    regex
+}
+
+// Levenshtein distance between `a` and `b` in characters, or optimal string alignment distance
+// if `transpose` is true
+fn edit_distance(a: &str, b: &str, transpose: bool) -> u32 {
+    let (a, b) = (a.chars().collect::<Vec<char>>(), b.chars().collect::<Vec<char>>());
+    // rows of distance matrix for b[..j] against a[..i-2], a[..i-1] and a[..i]
+    let mut prev2 = vec![0u32; b.len() + 1];
+    let mut prev = (0..=b.len() as u32).collect::<Vec<u32>>();
+    let mut curr = vec![0u32; b.len() + 1];
+    for i in 1..=a.len() {
+        curr[0] = i as u32;
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            curr[j] = (prev[j] + 1).min(curr[j - 1] + 1).min(prev[j - 1] + cost);
+            if transpose && i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                curr[j] = curr[j].min(prev2[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut prev2, &mut prev);
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    prev[b.len()]
+}
+
+#[test]
+fn test_glob_to_regex() {
+    assert_eq!(glob_to_regex("c?t"), "c.t");
+    assert_eq!(glob_to_regex("*tion"), ".*tion");
+    assert_eq!(glob_to_regex("[!ab]c.d"), "[^ab]c\\.d");
+    assert_eq!(glob_to_regex("a[b"), "a\\[b");
+}
+
+#[test]
+fn test_edit_distance() {
+    assert_eq!(edit_distance("hello", "hello", false), 0);
+    assert_eq!(edit_distance("hello", "hallo", false), 1);
+    assert_eq!(edit_distance("", "abc", false), 3);
+    assert_eq!(edit_distance("ab", "ba", false), 2);
+    assert_eq!(edit_distance("ab", "ba", true), 1);
+    assert_eq!(edit_distance("recieve", "receive", true), 1);
+    assert_eq!(edit_distance("café", "cafe", false), 1);
+}
+
+#[test]
+fn test_lookup_transpose() {
+    let keys = [("internationalisation", 1), ("internationalization", 2), ("internatoinalisation", 3)];
+    let idx = FSTIndex::build(&mut keys.iter().map(|(k, v)| (k.to_string(), *v)).collect::<Vec<_>>().iter(), KeyNormalization::NfkcFold).unwrap();
+    let options = LookupOptions { max_distance: Some(2), transpose: true, ..Default::default() };
+    // automaton of distance 3 is too large for the long keyword, so it falls back to distance 2
+    assert!(Levenshtein::new("internatoinalization", 3).is_err());
+    let words = idx.lookup("internatoinalization", &options).unwrap();
+    assert_eq!(words, vec![("internationalization".to_string(), 2), ("internatoinalisation".to_string(), 3)]);
+    let words = idx.lookup("intrenationalization", &LookupOptions { max_distance: Some(1), ..options }).unwrap();
+    assert_eq!(words, vec![("internationalization".to_string(), 2)]);
+}
+
+#[test]
+fn test_normalize_regex_literals() {
+    let norm = KeyNormalization::NfkcFold;
+    assert_eq!(normalize_regex_literals("Café.*", norm), "cafe.*");
+    assert_eq!(normalize_regex_literals(r"(?U)A\W+\pL\PN\p{Greek}\x4A", norm), r"(?U)a\W+\pL\PN\p{Greek}\x4A");
+    assert_eq!(normalize_regex_literals("(?P<Name>X)(?i:Y)(?-U)[A-Z]", norm), "(?P<Name>x)(?i:y)(?-U)[a-z]");
+    assert!(regex::Regex::new(&normalize_regex_literals(r"\pLÉ", norm)).is_ok());
+}
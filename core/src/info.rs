@@ -0,0 +1,432 @@
+/// This module collects summary of dictionaries for `wikit dict --info`
+///
+/// The summary is printed as `key: value` lines by default, or serialized into JSON for scripts.
+
+use crate::error::{WikitError, Context, WikitResult};
+use crate::elog;
+use crate::index::{IndexFormat, KeyNormalization};
+use crate::mdict;
+use crate::reader::WikitSource;
+use crate::wikit::{LocalDictionary, WikitSection};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+use serde::Serialize;
+
+/// Files of wikit source directory in the order they are reported
+const WIKIT_SOURCE_FILES: [&str; 3] = ["header.wikit.txt", "body.wikit.txt", "preview.wikit.txt"];
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "format", rename_all = "lowercase")]
+pub enum DictInfo {
+    Mdx(MdxInfo),
+    Wikit(WikitInfo),
+    Sqlite(SqliteInfo),
+    #[serde(rename = "wikit-source")]
+    Source(SourceInfo),
+}
+
+#[derive(Debug, Serialize)]
+pub struct MdxInfo {
+    pub path: String,
+    // file size in bytes
+    pub size: u64,
+    // value of `GeneratedByEngineVersion` header
+    pub version: String,
+    pub encoding: String,
+    // value of `Encrypted` header, see [mdict::MdxReader::encryption]
+    pub encrypted: u32,
+    pub entries: usize,
+    pub record_blocks: usize,
+    pub header: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WikitInfo {
+    pub path: String,
+    // file size in bytes
+    pub size: u64,
+    pub version: u32,
+    pub name: String,
+    pub desc: String,
+    pub index_format: IndexFormat,
+    pub key_normalization: KeyNormalization,
+    // number of index keys including aliases
+    pub entries: usize,
+    pub resources: usize,
+    pub fulltext: bool,
+    // size of script and style in bytes
+    pub script_size: usize,
+    pub style_size: usize,
+    // zero if the dictionary has no checksums, which is the case before version 0x03
+    pub chunk_size: u32,
+    // non-empty sections in the order of [WikitSection]
+    pub sections: Vec<SectionInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SectionInfo {
+    pub name: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SqliteInfo {
+    pub path: String,
+    // file size in bytes
+    pub size: u64,
+    // rows of `wikit` table created from `.wikit.txt`, none if there is no such table
+    pub entries: Option<u64>,
+    pub tables: Vec<TableInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TableInfo {
+    pub name: String,
+    pub rows: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceInfo {
+    pub path: String,
+    // name, description and author come from the `info` item
+    pub name: String,
+    pub desc: String,
+    pub author: String,
+    pub files: Vec<SourceFileInfo>,
+    // subdirectories such as `audios`
+    pub media: Vec<MediaInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceFileInfo {
+    pub name: String,
+    // number of items of each type such as `word`, `js` and `css`
+    pub items: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MediaInfo {
+    pub name: String,
+    pub files: usize,
+    // total size of files in bytes
+    pub size: u64,
+}
+
+impl DictInfo {
+    /// Collect summary of mdx file, only key index is parsed and records are not uncompressed
+    pub fn mdx<P>(path: P) -> WikitResult<Self> where P: AsRef<Path> {
+        let path = path.as_ref();
+        let reader = mdict::MdxReader::open(path)?;
+        Ok(DictInfo::Mdx(MdxInfo {
+            path: path.display().to_string(),
+            size: path.metadata()?.len(),
+            version: reader.header.get("GeneratedByEngineVersion").cloned().unwrap_or_default(),
+            encoding: reader.encoding().to_string(),
+            encrypted: reader.encryption(),
+            entries: reader.len(),
+            record_blocks: reader.record_blocks(),
+            header: reader.header.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+        }))
+    }
+
+    /// Collect summary of wikit dictionary
+    pub fn wikit<P>(path: P) -> WikitResult<Self> where P: AsRef<Path> {
+        let path = path.as_ref();
+        let dict = LocalDictionary::load(path)?;
+        let head = &dict.head;
+        let sections = [
+            (WikitSection::Data, head.dbase, head.dsz),
+            (WikitSection::Index, head.ibase, head.isz),
+            (WikitSection::ResourceIndex, head.ribase, head.risz),
+            (WikitSection::Fulltext, head.pbase, head.psz),
+            (WikitSection::FulltextIndex, head.fibase, head.fisz),
+            (WikitSection::Checksum, head.cbase, head.csz),
+        ];
+        Ok(DictInfo::Wikit(WikitInfo {
+            path: path.display().to_string(),
+            size: path.metadata()?.len(),
+            version: head.version,
+            name: head.name.clone(),
+            desc: head.desc.clone(),
+            index_format: head.ifmt.clone(),
+            key_normalization: head.knorm,
+            entries: dict.len(),
+            resources: dict.resource_len(),
+            fulltext: dict.has_fulltext(),
+            script_size: head.script.len(),
+            style_size: head.style.len(),
+            chunk_size: head.chunksz,
+            sections: sections.iter().filter(|x| x.2 > 0).map(|(section, offset, size)| SectionInfo {
+                name: section.to_string(),
+                offset: *offset,
+                size: *size,
+            }).collect(),
+        }))
+    }
+
+    /// Collect summary of sqlite database such as the one created from `.wikit.txt`
+    pub fn sqlite<P>(path: P) -> WikitResult<Self> where P: AsRef<Path> {
+        let path = path.as_ref();
+        // opening a missing file in read-write mode creates an empty database
+        let db = rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let names = db.prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut tables = vec![];
+        for name in names {
+            let sql = format!("SELECT COUNT(*) FROM \"{}\"", name.replace('"', "\"\""));
+            let rows = db.query_row(&sql, [], |row| row.get::<_, i64>(0))?;
+            tables.push(TableInfo { name, rows: rows as u64 });
+        }
+        Ok(DictInfo::Sqlite(SqliteInfo {
+            path: path.display().to_string(),
+            size: path.metadata()?.len(),
+            entries: tables.iter().find(|x| x.name == "wikit").map(|x| x.rows),
+            tables,
+        }))
+    }
+
+    /// Collect summary of wikit source directory which has `header.wikit.txt`, `body.wikit.txt`
+    /// and `preview.wikit.txt`
+    pub fn source<P>(dir: P) -> WikitResult<Self> where P: AsRef<Path> {
+        let dir = dir.as_ref();
+        let mut info = SourceInfo {
+            path: dir.display().to_string(),
+            name: "".to_string(),
+            desc: "".to_string(),
+            author: "".to_string(),
+            files: vec![],
+            media: vec![],
+        };
+        for name in WIKIT_SOURCE_FILES {
+            let path = dir.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            let mut items = BTreeMap::new();
+            for item in WikitSource::new(File::open(&path)?) {
+                if item.header.typ == "info" {
+                    let value = item.body.parse::<toml::Value>()?;
+                    let field = |key: &str| match value.get(key) {
+                        Some(toml::Value::String(x)) => x.trim().to_string(),
+                        Some(toml::Value::Array(x)) => {
+                            x.iter().filter_map(|x| x.as_str()).collect::<Vec<_>>().join(", ")
+                        },
+                        _ => "".to_string(),
+                    };
+                    info.desc = field("desc");
+                    info.author = field("author");
+                    info.name = item.header.name.clone();
+                }
+                *items.entry(item.header.typ).or_insert(0) += 1;
+            }
+            info.files.push(SourceFileInfo { name: name.to_string(), items });
+        }
+        if info.files.is_empty() {
+            return Err(WikitError::new(format!("{} is not a wikit source directory", dir.display())));
+        }
+
+        let mut subdirs = std::fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .map(|x| x.path())
+            .filter(|x| x.is_dir())
+            .collect::<Vec<_>>();
+        subdirs.sort();
+        for subdir in subdirs {
+            let (files, size) = count_files(&subdir)
+                .context(elog!("failed to read {}", subdir.display()))?;
+            let name = subdir.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+            info.media.push(MediaInfo { name, files, size });
+        }
+        Ok(DictInfo::Source(info))
+    }
+
+    pub fn to_json(&self) -> WikitResult<String> {
+        Ok(serde_json::to_string_pretty(self).context(elog!("failed to serialize dictionary information"))?)
+    }
+}
+
+// Count files under `dir` recursively and return `(number of files, total size)`
+fn count_files(dir: &Path) -> std::io::Result<(usize, u64)> {
+    let (mut files, mut size) = (0, 0);
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            let (subfiles, subsize) = count_files(&path)?;
+            files += subfiles;
+            size += subsize;
+        } else {
+            files += 1;
+            size += path.metadata()?.len();
+        }
+    }
+    Ok((files, size))
+}
+
+// Write `key: value` line, the continuation lines of value are indented under the key
+fn write_field<V>(f: &mut fmt::Formatter, indent: usize, key: &str, value: V) -> fmt::Result where V: fmt::Display {
+    let pad = " ".repeat(indent);
+    let value = value.to_string();
+    let value = value.trim().replace('\n', &format!("\n{}    ", pad));
+    writeln!(f, "{}{}: {}", pad, key, value)
+}
+
+impl fmt::Display for DictInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DictInfo::Mdx(x) => {
+                write_field(f, 0, "format", "mdx")?;
+                write_field(f, 0, "path", &x.path)?;
+                write_field(f, 0, "size", format!("{} bytes", x.size))?;
+                write_field(f, 0, "version", &x.version)?;
+                write_field(f, 0, "encoding", &x.encoding)?;
+                write_field(f, 0, "encrypted", x.encrypted)?;
+                write_field(f, 0, "entries", x.entries)?;
+                write_field(f, 0, "record blocks", x.record_blocks)?;
+                writeln!(f, "header:")?;
+                for (k, v) in x.header.iter() {
+                    write_field(f, 4, k, v)?;
+                }
+            },
+            DictInfo::Wikit(x) => {
+                write_field(f, 0, "format", "wikit")?;
+                write_field(f, 0, "path", &x.path)?;
+                write_field(f, 0, "size", format!("{} bytes", x.size))?;
+                write_field(f, 0, "version", format!("{:#04x}", x.version))?;
+                write_field(f, 0, "name", &x.name)?;
+                write_field(f, 0, "description", &x.desc)?;
+                write_field(f, 0, "index format", format!("{:?}", x.index_format))?;
+                write_field(f, 0, "key normalization", format!("{:?}", x.key_normalization))?;
+                write_field(f, 0, "entries", x.entries)?;
+                write_field(f, 0, "resources", x.resources)?;
+                write_field(f, 0, "full-text index", if x.fulltext { "yes" } else { "no" })?;
+                write_field(f, 0, "script", format!("{} bytes", x.script_size))?;
+                write_field(f, 0, "style", format!("{} bytes", x.style_size))?;
+                if x.chunk_size > 0 {
+                    write_field(f, 0, "checksum chunk", format!("{} bytes", x.chunk_size))?;
+                }
+                writeln!(f, "sections:")?;
+                for section in x.sections.iter() {
+                    write_field(f, 4, &section.name, format!("offset {:#x}, {} bytes", section.offset, section.size))?;
+                }
+            },
+            DictInfo::Sqlite(x) => {
+                write_field(f, 0, "format", "sqlite")?;
+                write_field(f, 0, "path", &x.path)?;
+                write_field(f, 0, "size", format!("{} bytes", x.size))?;
+                if let Some(entries) = x.entries {
+                    write_field(f, 0, "entries", entries)?;
+                }
+                writeln!(f, "tables:")?;
+                for table in x.tables.iter() {
+                    write_field(f, 4, &table.name, format!("{} rows", table.rows))?;
+                }
+            },
+            DictInfo::Source(x) => {
+                write_field(f, 0, "format", "wikit source")?;
+                write_field(f, 0, "path", &x.path)?;
+                write_field(f, 0, "name", &x.name)?;
+                write_field(f, 0, "description", &x.desc)?;
+                write_field(f, 0, "author", &x.author)?;
+                writeln!(f, "files:")?;
+                for file in x.files.iter() {
+                    let items = file.items.iter().map(|(typ, n)| format!("{} {}", n, typ)).collect::<Vec<_>>();
+                    write_field(f, 4, &file.name, items.join(", "))?;
+                }
+                if !x.media.is_empty() {
+                    writeln!(f, "media:")?;
+                    for media in x.media.iter() {
+                        write_field(f, 4, &media.name, format!("{} files, {} bytes", media.files, media.size))?;
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dict_info() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let srcdir = tmpdir.path().join("dict");
+        std::fs::create_dir_all(srcdir.join("audios")).unwrap();
+        std::fs::write(srcdir.join("audios").join("apple.ogg"), b"ogg").unwrap();
+        std::fs::write(srcdir.join("header.wikit.txt"), [
+            r#"("#,
+            r#"    "name": "demo","#,
+            r#"    "type": "info","#,
+            r#"    "mime": "application/toml","#,
+            r#") {"#,
+            r#"    desc = '''"#,
+            r#"    a demo dictionary"#,
+            r#"    '''"#,
+            r#"    author = ["alice", "bob"]"#,
+            r#"}"#,
+            r#"("#,
+            r#"    "name": "style.css","#,
+            r#"    "type": "css","#,
+            r#"    "mime": "text/css","#,
+            r#") {"#,
+            r#"    p { color: red; }"#,
+            r#"}"#,
+        ].join("\n")).unwrap();
+        std::fs::write(srcdir.join("body.wikit.txt"), [
+            r#"("#,
+            r#"    "name": "apple","#,
+            r#"    "type": "word","#,
+            r#"    "mime": "text/html","#,
+            r#") {"#,
+            r#"    <p>apple</p>"#,
+            r#"}"#,
+            r#"("#,
+            r#"    "name": "banana","#,
+            r#"    "type": "word","#,
+            r#"    "mime": "text/html","#,
+            r#") {"#,
+            r#"    <p>banana</p>"#,
+            r#"}"#,
+        ].join("\n")).unwrap();
+
+        let info = DictInfo::source(&srcdir).unwrap();
+        match &info {
+            DictInfo::Source(x) => {
+                assert_eq!(x.name, "demo");
+                assert_eq!(x.desc, "a demo dictionary");
+                assert_eq!(x.author, "alice, bob");
+                assert_eq!(x.files.len(), 2);
+                assert_eq!(x.files[0].items.get("css"), Some(&1));
+                assert_eq!(x.files[1].items.get("word"), Some(&2));
+                assert_eq!((x.media[0].name.as_str(), x.media[0].files, x.media[0].size), ("audios", 1, 3));
+            },
+            _ => panic!("unexpected information: {:?}", info),
+        }
+        assert!(info.to_string().contains("body.wikit.txt: 2 word"));
+        let json: serde_json::Value = serde_json::from_str(&info.to_json().unwrap()).unwrap();
+        assert_eq!(json["format"], "wikit-source");
+        assert!(DictInfo::source(tmpdir.path()).is_err());
+
+        let dbpath = tmpdir.path().join("dict.sqlite");
+        let db = rusqlite::Connection::open(&dbpath).unwrap();
+        db.execute("CREATE TABLE wikit (word TEXT PRIMARY KEY, meaning TEXT NOT NULL)", []).unwrap();
+        for word in ["apple", "banana", "cherry"] {
+            db.execute("INSERT INTO wikit (word, meaning) VALUES (?1, ?1)", [word]).unwrap();
+        }
+        drop(db);
+        match DictInfo::sqlite(&dbpath).unwrap() {
+            DictInfo::Sqlite(x) => {
+                assert_eq!(x.entries, Some(3));
+                assert_eq!(x.tables.len(), 1);
+            },
+            info => panic!("unexpected information: {:?}", info),
+        }
+    }
+}
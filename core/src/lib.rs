@@ -6,6 +6,8 @@ pub mod reader;
 pub mod util;
 pub mod router;
 pub mod index;
+pub mod info;
+pub mod fulltext;
 pub mod wikit;
 pub mod cache;
 pub mod crypto;
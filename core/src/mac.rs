@@ -1,11 +1,12 @@
 use crate::error::{AnyResult, Context};
-use crate::{util, config, elog};
+use crate::{util, config, elog, mdict};
+use crate::wikit::WikitDictProfile;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::env::consts;
 use std::fs::File;
-use std::io::{Write, BufWriter};
-use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom, Write, BufWriter};
+use std::collections::HashMap;
 
 use dialoguer::Input;
 
@@ -42,29 +43,70 @@ fn ensure_mac_environment() -> AnyResult<()> {
     Ok(())
 }
 
-pub fn create_mac_dictionary<I, P>(src: I, input: P, output: P, css: Option<P>) -> AnyResult<()>
-    where I: Iterator<Item = (String, String)>, P: AsRef<Path>,
-{
-    ensure_mac_environment().context(elog!("mac environment requirements are not met"))?;
+// Files of Apple Dictionary Development Kit project, the `OtherResources` directory is copied
+// into the dictionary bundle by the kit
+const MAC_PROJECT_XML: &str = "Dictionary.xml";
+const MAC_PROJECT_CSS: &str = "Dictionary.css";
+const MAC_PROJECT_PLIST: &str = "Info.plist";
+const MAC_PROJECT_MAKEFILE: &str = "Makefile";
+const MAC_PROJECT_RESOURCES: &str = "OtherResources";
+
+/// Apple Dictionary Development Kit project created by [create_mac_project]
+#[derive(Debug, Clone)]
+pub struct MacProject {
+    /// Project directory
+    pub dir: PathBuf,
+    /// Dictionary name, the kit builds `name.dictionary`
+    pub name: String,
+}
 
+/// Create macos dictionary from `(word, meaning)` pairs of `src`
+///
+/// This is [create_mac_project] followed by [build_mac_dictionary], the project is created in
+/// directory `wikit` alongside with `input` and removed after the dictionary is built.
+pub fn create_mac_dictionary<I, P>(src: I, input: P, output: P, css: Option<P>, policy: &util::DuplicatePolicy, profile: &WikitDictProfile) -> AnyResult<()>
+    where I: Iterator<Item = AnyResult<(String, String)>>, P: AsRef<Path>,
+{
     let input = std::fs::canonicalize(input.as_ref()).context(elog!("cannot find input file"))?;
     let workdir = input.parent().context(elog!("cannot get working directory"))?.join("wikit");
-    let workdir = workdir.as_path();
-    std::fs::create_dir_all(workdir).context(elog!("cannot create working directory"))?;
+    let dname = output.as_ref().file_stem()
+        .context(elog!("cannot output filename"))?
+        .to_str().context(elog!("cannot convert osstr to str"))?;
+    let project = create_mac_project(src, input.as_path(), workdir.join(dname.to_string() + ".ddk").as_path(), css.as_ref().map(|x| x.as_ref()), policy, profile)?;
+    build_mac_dictionary(&project, output.as_ref())?;
+    std::fs::remove_dir_all(&workdir).context(elog!("cannot remove working directory {}", workdir.display()))?;
+
+    println!("[+] Copy dictionary generated at '{}' into ~/Library/Dictionaries", output.as_ref().display());
+    Ok(())
+}
 
-    let input_stem = input.file_stem()
-        .context(elog!("cannot get input filename"))?
+/// Create Apple Dictionary Development Kit project in directory `project` from `(word, meaning)`
+/// pairs of `src`, which works on any OS
+///
+/// The project has `Dictionary.xml`, `Dictionary.css`, `Info.plist`, a `Makefile` and media
+/// resources of `input` (see [WikitDictProfile::media_dir]) in `OtherResources`, the dictionary
+/// name is the file stem of `project`. The name, version and authors of dictionary are taken
+/// from `profile`, its style is appended to the default style unless `css` is given, and its mdx
+/// style sheet is expanded into meanings.
+pub fn create_mac_project<I, P>(src: I, input: P, project: P, css: Option<P>, policy: &util::DuplicatePolicy, profile: &WikitDictProfile) -> AnyResult<MacProject>
+    where I: Iterator<Item = AnyResult<(String, String)>>, P: AsRef<Path>,
+{
+    let workdir = project.as_ref();
+    let dname = workdir.file_stem()
+        .context(elog!("cannot get project name"))?
         .to_str().context(elog!("cannot convert osstr to str"))?;
+    std::fs::create_dir_all(workdir).context(elog!("cannot create project directory"))?;
 
-    let dcss = if let Some(css) = css {
-        css.as_ref().to_path_buf()
+    let css_path = workdir.join(MAC_PROJECT_CSS);
+    if let Some(css) = css {
+        std::fs::copy(css.as_ref(), &css_path).context(elog!("cannot copy css file: {}", css.as_ref().display()))?;
     } else {
-        let css_content = r#"
+        let css_content = format!(r#"
             @charset "UTF-8";
             @namespace d url(http://www.apple.com/DTDs/DictionaryService-1.0.rng);
-        "#;
-        let css = workdir.join("".to_string() + input_stem + ".css");
-        let mut fcss = File::create(&css).context(elog!("cannot create css file: {}", css.display()))?;
+            {}
+        "#, profile.style());
+        let mut fcss = File::create(&css_path).context(elog!("cannot create css file: {}", css_path.display()))?;
         for line in css_content.lines() {
             let line = line.trim();
             if line.len() > 0 {
@@ -72,15 +114,10 @@ pub fn create_mac_dictionary<I, P>(src: I, input: P, output: P, css: Option<P>)
                 fcss.write_all(b"\n")?;
             }
         }
-        css
-    };
-
-    let dname = output.as_ref().file_stem()
-        .context(elog!("cannot output filename"))?
-        .to_str().context(elog!("cannot convert osstr to str"))?;
+    }
 
-    let dsrc = {
-        let xml = workdir.join("".to_string() + input_stem + ".xml");
+    {
+        let xml = workdir.join(MAC_PROJECT_XML);
         let fxml = File::create(&xml).context(elog!("cannot create xml file: {}", xml.display()))?;
         let mut writer = BufWriter::new(fxml);
 
@@ -89,22 +126,45 @@ pub fn create_mac_dictionary<I, P>(src: I, input: P, output: P, css: Option<P>)
         writer.write(r#"<d:dictionary xmlns="http://www.w3.org/1999/xhtml" xmlns:d="http://www.apple.com/DTDs/DictionaryService-1.0.rng">"#.as_bytes())?;
         writer.write(b"\n")?;
 
-        let mut bitmap = BTreeMap::new();
+        // meanings are spilled into a temporary file as they are read and only their positions
+        // are kept in memory, they are read back when the duplicate words are merged
         let nullchar = char::from(0);
-        for (word, meaning) in src {
-            let (word, meaning) = (word.trim_matches(nullchar), meaning.trim_matches(nullchar));
-            // Remove duplicate word
-            match bitmap.get(word) {
-                Some(_) => continue,
-                None => bitmap.insert(word.to_string(), true),
-            };
+        let mut spill = BufWriter::new(tempfile::tempfile().context(elog!("cannot create temporary file"))?);
+        let mut words = vec![];
+        let mut offset = 0u64;
+        for entry in src {
+            let (word, meaning) = entry?;
+            let meaning = meaning.trim_matches(nullchar);
+            spill.write_all(meaning.as_bytes())?;
+            words.push((word.trim_matches(nullchar).to_string(), (offset, meaning.len())));
+            offset += meaning.len() as u64;
+        }
+        let mut spill = spill.into_inner().map_err(|e| e.into_error())?;
+        let (words, dupcnt) = util::group_duplicates(words, policy);
+        let styles = mdict::parse_stylesheet(&profile.stylesheet());
+        // entry id must be unique, so the n-th definition of a word kept by policy `all` is
+        // identified by `word_n`
+        let mut idmap: HashMap<String, usize> = HashMap::new();
+        for (word, positions) in words.iter() {
+            let mut meanings = Vec::with_capacity(positions.len());
+            for (offset, len) in positions.iter() {
+                let mut buf = vec![0; *len];
+                spill.seek(SeekFrom::Start(*offset))?;
+                spill.read_exact(&mut buf)?;
+                meanings.push(String::from_utf8(buf).context(elog!("meaning of {} is not valid utf-8", word))?);
+            }
+            let meaning = mdict::apply_stylesheet(&policy.join(meanings), &styles);
+            let (word, meaning) = (word.as_str(), meaning.as_str());
+            let n = idmap.entry(word.to_string()).or_insert(0);
+            *n += 1;
+            let entry_id = if *n > 1 { format!("{}_{}", word, n) } else { word.to_string() };
             let entry = format!(
                 r#"<d:entry id="{entry_id}" d:title="{entry_title}">
                        <d:index d:value="{entry_index}"/>
                        <h1>{entry_title}</h1>
                        {entry_body}
                    </d:entry>"#,
-                entry_id=word,
+                entry_id=entry_id,
                 entry_title=word,
                 entry_index=word,
                 entry_body=meaning,
@@ -119,12 +179,14 @@ pub fn create_mac_dictionary<I, P>(src: I, input: P, output: P, css: Option<P>)
         writer.write(b"</d:dictionary>")?;
         writer.write(b"\n")?;
         writer.flush()?;
+        if dupcnt > 0 {
+            println!("[+] {} words have multiple definitions which are handled by policy {:?}", dupcnt, policy);
+        }
+    }
 
-        xml
-    };
-
-    let dplist = {
-        let plist = workdir.join("".to_string() + input_stem + ".plist");
+    {
+        let author = profile.author();
+        let plist = workdir.join(MAC_PROJECT_PLIST);
         let mut fplist = File::create(&plist).context(elog!("cannot create plist file: {}", plist.display()))?;
         let content = format!(r#"
                 <?xml version="1.0" encoding="UTF-8"?>
@@ -149,9 +211,9 @@ pub fn create_mac_dictionary<I, P>(src: I, input: P, output: P, css: Option<P>)
                 </plist>
             "#,
             identifier = "created.by.wikit",
-            name = dname,
-            version = "1.0",
-            organization = "wikit: https://github.com/ikey4u/wikit",
+            name = if profile.name().trim().len() > 0 { profile.name() } else { dname },
+            version = if profile.version().trim().len() > 0 { profile.version() } else { "1.0" },
+            organization = if author.trim().len() > 0 { author.as_str() } else { "wikit: https://github.com/ikey4u/wikit" },
         );
         for line in content.lines() {
             let line = line.trim();
@@ -160,38 +222,149 @@ pub fn create_mac_dictionary<I, P>(src: I, input: P, output: P, css: Option<P>)
                 fplist.write_all(b"\n")?;
             }
         }
-        plist
-    };
-
-    let dictpath = {
-        let ddkdir = config::get_config_dir()?.join("macddk");
-        let builder = ddkdir.join("bin").join("build_dict.sh");
-        // There must no spaces in directory DICT_DEV_KIT_OBJ_DIR
-        let tmpdir = std::env::temp_dir().join("wikit");
-        let tmpdir = tmpdir.as_path();
-        std::fs::create_dir_all(tmpdir).context(elog!("cannot create working directory"))?;
-        let tmpdir: String = format!("{}", tmpdir.display());
-        let envs = vec![("DICT_DEV_KIT_OBJ_DIR".into(), tmpdir.clone())];
-        let cmd = format!(
-            "'{}' '{}' '{}' '{}' '{}'",
-            builder.display(), dname, dsrc.display(), dcss.display(), dplist.display(),
-        );
-        println!("[+] Running Mac DDK ...");
-        let msg = util::runcmd(&cmd, Some(envs)).context(elog!("cannot run command {}", cmd))?;
-        println!("{}", msg);
-
-        let cmds = vec![
-            format!("rm -rf '{}'", output.as_ref().display()),
-            format!("ditto --noextattr --norsrc '{}/{}.dictionary' '{}'", tmpdir, dname, output.as_ref().display()),
-            format!("rm -rf '{}' '{}'", tmpdir, workdir.display()),
-        ];
-        for cmd in cmds {
-            util::runcmd(&cmd, None)?;
-        }
+    }
+
+    {
+        // the default kit directory is where `ensure_mac_environment` installs it on macOS, it
+        // can be overridden by `make DICT_BUILD_TOOL_DIR=/path/to/kit`
+        let makefile = workdir.join(MAC_PROJECT_MAKEFILE);
+        let name = format!("DICT_NAME = \"{}\"", dname.replace('$', "$$").replace('"', "\\\""));
+        let content = [
+            name.as_str(),
+            &format!("DICT_SRC_PATH = {}", MAC_PROJECT_XML),
+            &format!("CSS_PATH = {}", MAC_PROJECT_CSS),
+            &format!("PLIST_PATH = {}", MAC_PROJECT_PLIST),
+            "DICT_BUILD_OPTS =",
+            "",
+            "DICT_BUILD_TOOL_DIR ?= $(HOME)/Library/Application Support/wikit/macddk",
+            "DICT_BUILD_TOOL_BIN = $(DICT_BUILD_TOOL_DIR)/bin",
+            "DICT_DEV_KIT_OBJ_DIR ?= ./objects",
+            "export DICT_DEV_KIT_OBJ_DIR",
+            "DESTINATION_FOLDER = ~/Library/Dictionaries",
+            "",
+            "all:",
+            "\t\"$(DICT_BUILD_TOOL_BIN)/build_dict.sh\" $(DICT_BUILD_OPTS) $(DICT_NAME) $(DICT_SRC_PATH) $(CSS_PATH) $(PLIST_PATH)",
+            "",
+            "install:",
+            "\tmkdir -p $(DESTINATION_FOLDER)",
+            "\tditto --noextattr --norsrc \"$(DICT_DEV_KIT_OBJ_DIR)/\"$(DICT_NAME).dictionary $(DESTINATION_FOLDER)/$(DICT_NAME).dictionary",
+            "\ttouch $(DESTINATION_FOLDER)",
+            "",
+            "clean:",
+            "\trm -rf \"$(DICT_DEV_KIT_OBJ_DIR)\"",
+            "",
+            ".PHONY: all install clean",
+            "",
+        ].join("\n");
+        std::fs::write(&makefile, content).context(elog!("cannot create makefile: {}", makefile.display()))?;
+    }
+
+    let mediadir = profile.media_dir(input.as_ref());
+    if mediadir.is_dir() {
+        let resdir = workdir.join(MAC_PROJECT_RESOURCES);
+        let count = copy_dir(&mediadir, &resdir).context(elog!("cannot copy media from {}", mediadir.display()))?;
+        println!("[+] {} media files are copied into {}", count, resdir.display());
+    }
 
-        format!("'{}'", output.as_ref().display())
-    };
+    Ok(MacProject { dir: workdir.to_path_buf(), name: dname.to_string() })
+}
+
+/// Build macos dictionary `output` from Apple Dictionary Development Kit `project`, this stage
+/// requires macOS and the kit is installed on demand
+pub fn build_mac_dictionary<P>(project: &MacProject, output: P) -> AnyResult<()> where P: AsRef<Path> {
+    ensure_mac_environment().context(elog!("mac environment requirements are not met"))?;
+
+    let ddkdir = config::get_config_dir()?.join("macddk");
+    let builder = ddkdir.join("bin").join("build_dict.sh");
+    // There must no spaces in directory DICT_DEV_KIT_OBJ_DIR
+    let tmpdir = std::env::temp_dir().join("wikit");
+    let tmpdir = tmpdir.as_path();
+    std::fs::create_dir_all(tmpdir).context(elog!("cannot create working directory"))?;
+    let tmpdir: String = format!("{}", tmpdir.display());
+    let envs = vec![("DICT_DEV_KIT_OBJ_DIR".into(), tmpdir.clone())];
+    // the kit looks for `OtherResources` in the current directory
+    let cmd = format!(
+        "'{}' '{}' '{}' '{}' '{}'",
+        builder.display(), project.name, MAC_PROJECT_XML, MAC_PROJECT_CSS, MAC_PROJECT_PLIST,
+    );
+    println!("[+] Running Mac DDK ...");
+    let msg = util::runcmd_in(&cmd, Some(envs), Some(project.dir.as_path()))
+        .context(elog!("cannot run command {}", cmd))?;
+    println!("{}", msg);
+
+    let cmds = vec![
+        format!("rm -rf '{}'", output.as_ref().display()),
+        format!("ditto --noextattr --norsrc '{}/{}.dictionary' '{}'", tmpdir, project.name, output.as_ref().display()),
+        format!("rm -rf '{}'", tmpdir),
+    ];
+    for cmd in cmds {
+        util::runcmd(&cmd, None)?;
+    }
 
-    println!("[+] Copy dictionary generated at {} into ~/Library/Dictionaries", dictpath);
     Ok(())
 }
+
+// Copy files under `src` into `dst` recursively and return the number of copied files
+fn copy_dir(src: &Path, dst: &Path) -> AnyResult<usize> {
+    let mut count = 0;
+    std::fs::create_dir_all(dst)?;
+    for entry in std::fs::read_dir(src)? {
+        let path = entry?.path();
+        let target = dst.join(path.file_name().context(elog!("invalid path {}", path.display()))?);
+        if path.is_dir() {
+            count += copy_dir(&path, &target)?;
+        } else {
+            std::fs::copy(&path, &target)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_mac_project() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let input = tmpdir.path().join("demo.txt");
+        std::fs::write(&input, "").unwrap();
+        std::fs::create_dir_all(tmpdir.path().join("demo.media").join("images")).unwrap();
+        std::fs::write(tmpdir.path().join("demo.media").join("images").join("a.png"), b"png").unwrap();
+        std::fs::write(tmpdir.path().join("demo.toml"), [
+            r#"name = "Demo Dictionary""#,
+            r#"version = "2.1""#,
+            r#"authors = ["alice"]"#,
+            r#"distributors = []"#,
+            r#"description = "demo""#,
+            r#"homepage = """#,
+            r#"js = """#,
+            r#"css = "h1 { color: red; }""#,
+        ].join("\n")).unwrap();
+        let profile = WikitDictProfile::load(&input).unwrap();
+
+        let src = vec![
+            ("apple".to_string(), "<p>a fruit & a company</p>".to_string()),
+            ("banana".to_string(), "<p>a yellow fruit</p>".to_string()),
+        ];
+        let projdir = tmpdir.path().join("out").join("demo.ddk");
+        let project = create_mac_project(src.into_iter().map(Ok), input.as_path(), projdir.as_path(), None, &util::DuplicatePolicy::First, &profile).unwrap();
+        assert_eq!(project.name, "demo");
+        assert_eq!(project.dir, projdir);
+
+        let xml = std::fs::read_to_string(projdir.join(MAC_PROJECT_XML)).unwrap();
+        assert!(xml.contains(r#"<d:entry id="apple" d:title="apple">"#));
+        assert!(xml.contains("a fruit &amp; a company"));
+        assert!(xml.trim_end().ends_with("</d:dictionary>"));
+        let css = std::fs::read_to_string(projdir.join(MAC_PROJECT_CSS)).unwrap();
+        assert!(css.contains("h1 { color: red; }"));
+        let plist = std::fs::read_to_string(projdir.join(MAC_PROJECT_PLIST)).unwrap();
+        assert!(plist.contains("<string>Demo Dictionary</string>"));
+        assert!(plist.contains("<string>2.1</string>"));
+        let makefile = std::fs::read_to_string(projdir.join(MAC_PROJECT_MAKEFILE)).unwrap();
+        assert!(makefile.contains("DICT_NAME = \"demo\"\n"));
+        assert!(makefile.contains("all:\n\t\"$(DICT_BUILD_TOOL_BIN)/build_dict.sh\""));
+        assert_eq!(std::fs::read(projdir.join(MAC_PROJECT_RESOURCES).join("images").join("a.png")).unwrap(), b"png");
+    }
+}
@@ -3,34 +3,44 @@ use crate::error::{Context, AnyResult, NomResult};
 use crate::reader::MDXSource;
 use crate::config::MAX_MDX_ITEM_SIZE;
 use crate::util;
+use crate::index;
+use crate::config;
+use crate::crypto;
+use crate::wikit::WikitDictProfile;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::convert::TryInto;
-use std::io::{Read, BufReader, Write, Seek, SeekFrom};
+use std::io::{Read, BufWriter, Write};
 use std::fs::{File, OpenOptions};
-use std::path::{Path};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use sqlx::postgres::PgPoolOptions;
 use nom::number::streaming::{be_u32, le_u32};
-use nom::{regex, do_parse, tuple, map_res, take, count, take_until, pair, cond};
+use nom::{regex, do_parse, tuple, map_res, take, count, pair, cond};
 use compress::zlib;
 use adler::Adler32;
 use ripemd128::{Ripemd128, Digest};
-use encoding_rs::GB18030;
+use encoding_rs::{Encoding, GB18030, UTF_8, UTF_16LE, UTF_16BE};
 use chrono::{DateTime, Local};
 use indicatif::ProgressBar;
+use memmap::Mmap;
+use serde::{Deserialize, Serialize};
+use once_cell::sync::Lazy;
 
 #[derive(PartialEq)]
 pub enum ParseOption {
     OnlyHeader,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct MDXInfo {
     version: u32,
-    encoding: String,
+    encoding: &'static Encoding,
     integersz: u32,
     encid: u32,
+    // key to decrypt words layout which is derived from registration code
+    key: Option<[u8; 16]>,
 }
 
 #[derive(Debug, Default)]
@@ -66,19 +76,10 @@ fn mdx_decrypt(mut cipher: Vec<u8>, key: Vec<u8>) -> AnyResult<Vec<u8>> {
     Ok(cipher)
 }
 
+// Decode text by encoding of mdx, malformed characters are replaced by U+FFFD
 fn mdx_decode(mdxinfo: &MDXInfo, buffer: &[u8]) -> AnyResult<String> {
-    let word_text = match mdxinfo.encoding.as_str() {
-        "GB18030" => {
-            let (word_text, _encoding_used, _has_malformed_chars) = GB18030.decode(buffer);
-            word_text.to_string()
-        }
-        _ => {
-            String::from_utf8(buffer.to_vec())
-            .context(elog!("invalid utf8 word text"))?
-            .replace("\x00", "")
-        }
-    };
-    Ok(word_text)
+    let (word_text, _has_malformed_chars) = mdxinfo.encoding.decode_without_bom_handling(buffer);
+    Ok(word_text.replace("\x00", ""))
 }
 
 impl MDXInfo {
@@ -89,6 +90,11 @@ impl MDXInfo {
             return Err(elog!("[!] No engine version"));
         };
         let version = (version * 10.0) as u32;
+        // MDict publishes no specification of the 3.0 format, so it is rejected rather than
+        // decoded by a guessed layout
+        if version >= 30 {
+            return Err(elog!("mdx {} generated by MDict 3.0 engine is not supported", meta["GeneratedByEngineVersion"]));
+        }
 
         let integersz = if version < 20 {
             4
@@ -96,14 +102,14 @@ impl MDXInfo {
             8
         };
 
-        let encoding = if let Some(encoding) = meta.get("Encoding") {
-            if encoding.contains("GBK") || encoding.contains("GB2312") {
-                String::from("GB18030")
-            } else {
-                encoding.to_owned()
-            }
-        } else {
-            String::from("UTF-8")
+        // GBK and GB2312 are subsets of GB18030, and empty encoding means UTF-8
+        let encoding = match meta.get("Encoding").map(|x| x.trim()) {
+            Some(label) if label.to_uppercase().contains("GBK") || label.to_uppercase().contains("GB2312") => GB18030,
+            Some(label) if label.len() > 0 => Encoding::for_label(label.as_bytes()).unwrap_or_else(|| {
+                log::warn!("unknown encoding {}, UTF-8 is used", label);
+                UTF_8
+            }),
+            _ => UTF_8,
         };
 
         let encid = if let Some(encid) = meta.get("Encrypted") {
@@ -127,8 +133,70 @@ impl MDXInfo {
             encoding,
             integersz,
             encid,
+            key: None,
         })
     }
+
+    // Text encoded by UTF-16 is terminated by NUL of two bytes
+    fn utf16(&self) -> bool {
+        self.encoding == UTF_16LE || self.encoding == UTF_16BE
+    }
+
+    // Derive key from passcode of mdx `path` if its words layout is encrypted, see
+    // [config::MdxPasscode] for where the passcode comes from
+    fn unlock<P: AsRef<Path>>(&mut self, path: P) -> AnyResult<()> {
+        if self.encid & 1 == 1 {
+            let path = path.as_ref();
+            let passcode = config::find_passcode(path).context(elog!(
+                "{} is encrypted by the creator, registration code and user ID are required", path.display()
+            ))?;
+            self.key = Some(regcode_key(&passcode)?);
+        }
+        Ok(())
+    }
+}
+
+// Derive key from registration code and user ID which is email or device ID
+//
+// The registration code is encrypted by Salsa20/8 with the RIPEMD-128 digest of user ID, where
+// email is encoded by UTF-16LE and device ID is used as it is.
+fn regcode_key(passcode: &config::MdxPasscode) -> AnyResult<[u8; 16]> {
+    let regcode: [u8; 16] = hex::decode(passcode.regcode.trim()).ok()
+        .and_then(|x| x.try_into().ok())
+        .context(elog!("registration code must be 32 hex digits"))?;
+    let userid = passcode.userid.trim();
+    let userid = if userid.contains('@') {
+        userid.encode_utf16().flat_map(|x| x.to_le_bytes()).collect::<Vec<u8>>()
+    } else {
+        userid.as_bytes().to_vec()
+    };
+    let mut ripemd128 = Ripemd128::new();
+    ripemd128.input(userid);
+    let digest: [u8; 16] = ripemd128.result().to_vec().try_into()
+        .map_err(|_| elog!("invalid ripemd128 digest"))?;
+    crypto::salsa20_8(&digest, &regcode).try_into().map_err(|_| elog!("invalid registration key"))
+}
+
+// Split `buf` into word text terminated by NUL and the remained buffer after the terminator, the
+// terminator of UTF-16 text is two bytes at even offset
+fn split_word_text(buf: &[u8], utf16: bool) -> Option<(&[u8], &[u8])> {
+    let end = if utf16 {
+        buf.chunks_exact(2).position(|x| x == [0, 0])? * 2
+    } else {
+        buf.iter().position(|x| *x == 0)?
+    };
+    let termsz = if utf16 { 2 } else { 1 };
+    Some((&buf[..end], &buf[end + termsz..]))
+}
+
+// Get range of the `i`-th record in records of `recordsz` bytes, `keys` are sorted by offset
+fn record_range(keys: &[(String, u64)], i: usize, recordsz: usize) -> AnyResult<(usize, usize)> {
+    let start = keys[i].1 as usize;
+    let end = keys.get(i + 1).map(|x| x.1 as usize).unwrap_or(recordsz);
+    if start > end || end > recordsz {
+        return Err(elog!("record of {} is out of range", keys[i].0));
+    }
+    Ok((start, end))
 }
 
 struct MdxPacket<'a> {
@@ -154,18 +222,18 @@ impl<'a> MdxPacket<'a> {
     }
 }
 
-// parse_mdx will parse mdx file into list of (word, meaning) pair, all words are space trimed and
-// converted into lowercase.
-pub fn parse_mdx(mdxpath: &str, option: Option<ParseOption>) -> AnyResult<MDXDict> {
-    let mut mdict = MDXDict::default();
+// Escape attribute value of mdx header which is XML
+fn escape_header_value(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
 
-    let mut buf = Vec::new();
-    File::open(mdxpath).context(elog!("failed to open {}", mdxpath))?
-        .read_to_end(&mut buf)
-        .context(elog!("cannot read mdx file {}", mdxpath))?;
+fn unescape_header_value(value: &str) -> String {
+    value.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&amp;", "&")
+}
 
-    log::info!("[+] Parse header ...");
-    let mdict_header: NomResult<_> = do_parse!(&buf[..],
+// Parse header of mdx or mdd into `(remained buffer, header attributes)`
+fn parse_mdict_header(buf: &[u8]) -> AnyResult<(&[u8], HashMap<String, String>)> {
+    let mdict_header: NomResult<_> = do_parse!(buf,
         size: be_u32 >>
         meta: map_res!(take!(size + 4),
             |x: &[u8]| -> AnyResult<HashMap<String, String>> {
@@ -180,15 +248,21 @@ pub fn parse_mdx(mdxpath: &str, option: Option<ParseOption>) -> AnyResult<MDXDic
                     return Err(elog!("[x] Want adler32 sum is {:#x} but given {:#x}", meta_adler32_want, meta_adler32_give));
                 }
 
-                let metabuf: Vec<u16> = metabuf.chunks_exact(2).map(|a| u16::from_le_bytes([a[0], a[1]])).collect();
-                let metastr = String::from_utf16(&metabuf[..])
-                    .context(elog!("failed to get metastr"))?
-                    .replace("\x00", "");
-                let metare = regex::Regex::new(r#"\s{1}(\w+)="(.*?)""#)
+                // header is encoded by UTF-16LE, header of UTF-8 is accepted as well so that newer
+                // engine versions can be reported as unsupported
+                let metastr = if metabuf.get(1) == Some(&0) {
+                    let metabuf: Vec<u16> = metabuf.chunks_exact(2).map(|a| u16::from_le_bytes([a[0], a[1]])).collect();
+                    String::from_utf16(&metabuf[..]).context(elog!("failed to get metastr"))?
+                } else {
+                    String::from_utf8(metabuf.to_vec()).context(elog!("failed to get metastr"))?
+                };
+                let metastr = metastr.replace("\x00", "");
+                // value such as `StyleSheet` may span multiple lines
+                let metare = regex::Regex::new(r#"(?s)\s{1}(\w+)="(.*?)""#)
                     .context(elog!("regex error"))?;
                 let mut meta = HashMap::new();
                 for attr in metare.captures_iter(metastr.as_str()) {
-                    meta.insert(attr[1].to_string(), attr[2].to_string());
+                    meta.insert(attr[1].to_string(), unescape_header_value(&attr[2]));
                 }
 
                 Ok(meta)
@@ -196,16 +270,125 @@ pub fn parse_mdx(mdxpath: &str, option: Option<ParseOption>) -> AnyResult<MDXDic
         ) >> ( meta )
     );
     let (buf, meta) = mdict_header?;
-    mdict.header = meta;
-    log::info!("[+] Got header\n{:#x?}", mdict.header);
-    if let Some(option) = option {
-        if option == ParseOption::OnlyHeader {
-            return Ok(mdict);
+    Ok((buf, meta))
+}
+
+// Unpack data of key block or record block which is stored (0), compressed by LZO (1) or zlib (2)
+//
+// The pack type also tells how the block is encrypted, where bits 0-3 are compression, bits 4-7
+// are encryption method and bits 8-15 are the size of encrypted data at the beginning of block.
+fn mdx_unpack(mdxinfo: &MDXInfo, packet: &MdxPacket, unpacksz: u64) -> AnyResult<Vec<u8>> {
+    let compression = packet.packtype & 0xf;
+    let encryption = (packet.packtype >> 4) & 0xf;
+    let encsz = ((packet.packtype >> 8) & 0xff) as usize;
+    let decrypted;
+    let data = if encryption == 0 {
+        packet.data
+    } else {
+        // the key is derived from checksum of block if it is not given by header
+        let mut ripemd128 = Ripemd128::new();
+        ripemd128.input(packet.adler32.to_be_bytes());
+        let key: [u8; 16] = ripemd128.result().to_vec().try_into()
+            .map_err(|_| elog!("invalid ripemd128 digest"))?;
+        let (cipher, plain) = packet.data.split_at(encsz.min(packet.data.len()));
+        let cipher = match encryption {
+            1 => mdx_decrypt(cipher.to_vec(), key.to_vec())?,
+            2 => crypto::salsa20_8(&key, cipher),
+            _ => return Err(elog!("unknown block encryption method {}", encryption)),
+        };
+        decrypted = [&cipher[..], plain].concat();
+        &decrypted[..]
+    };
+    let data = match compression {
+        0 => data.to_vec(),
+        1 => {
+            minilzo_rs::LZO::init().context(elog!("failed to initialize minilzo"))?
+                .decompress_safe(data, unpacksz as usize)
+                .context(elog!("lzo decompress failed"))?
+        },
+        2 => {
+            let mut unpacked = vec![];
+            zlib::Decoder::new(data).read_to_end(&mut unpacked)
+                .context(elog!("zlib decoding failed"))?;
+            unpacked
+        },
+        _ => return Err(elog!("unknown compression type {}", compression)),
+    };
+    // the checksum of uncompressed data is given since version 2.0
+    if mdxinfo.version >= 20 || compression == 2 {
+        let mut adler = Adler32::new();
+        adler.write_slice(&data[..]);
+        if adler.checksum() != packet.adler32 {
+            return Err(elog!("wrong adler32 checksum {:#x}", packet.adler32));
         }
     }
+    Ok(data)
+}
+
+// Pack data into block with header `(packtype, adler32 of data)`, which is the reverse of `mdx_unpack`
+fn mdx_pack(data: &[u8], compression: MdxCompression, level: u32) -> AnyResult<Vec<u8>> {
+    let packed = match compression {
+        MdxCompression::None => data.to_vec(),
+        MdxCompression::Lzo => {
+            minilzo_rs::LZO::init().context(elog!("failed to initialize minilzo"))?
+                .compress(data)
+                .context(elog!("lzo compress failed"))?
+        },
+        MdxCompression::Zlib => {
+            let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::new(level.min(9)));
+            encoder.write_all(data).context(elog!("zlib encoding failed"))?;
+            encoder.finish().context(elog!("zlib encoding failed"))?
+        },
+    };
+    let mut adler = Adler32::new();
+    adler.write_slice(data);
+    let mut block = (compression as u32).to_le_bytes().to_vec();
+    block.extend(adler.checksum().to_be_bytes());
+    block.extend(packed);
+    Ok(block)
+}
+
+// Parse keys and locate record blocks of mdx or mdd into `(keys, blocks)`, where offsets of
+// `blocks` are relative to `buf` which follows the header
+fn parse_mdict_index(buf: &[u8], mdxinfo: &MDXInfo) -> AnyResult<(Vec<(String, u64)>, Vec<RecordBlock>)> {
+    let (remain, keys) = parse_mdict_keys(buf, mdxinfo)?;
+    let (infos, remain) = parse_record_table(remain, mdxinfo)?;
+    let (mut offset, mut start) = (buf.len() - remain.len(), 0);
+    let mut blocks = vec![];
+    for (packsz, unpacksz) in infos {
+        blocks.push(RecordBlock { offset, packsz, start, unpacksz });
+        offset += packsz as usize;
+        start += unpacksz;
+    }
+    if offset > buf.len() {
+        return Err(elog!("record blocks are out of range"));
+    }
+    Ok((keys, blocks))
+}
 
-    let mdxinfo = &MDXInfo::new(&mdict.header)?;
+// Split uncompressed key block into list of `(key text, record offset)`
+fn split_key_block(mdxinfo: &MDXInfo, mut data: &[u8]) -> AnyResult<Vec<(String, u64)>> {
+    let mut keys = vec![];
+    while data.len() > 0 {
+        let r: NomResult<_> = take!(data, mdxinfo.integersz);
+        let (remain, meaning_offset) = r.context(elog!("meaning_offset"))?;
+        let meaning_offset = bytes_to_u64(meaning_offset, true);
+        let (word_text, remain) = split_word_text(remain, mdxinfo.utf16())
+            .context(elog!("word text is not terminated"))?;
+        let word_text = mdx_decode(&mdxinfo, word_text)
+            .context(elog!(
+                "failed to decode {:x?} with encode {}",
+                word_text,
+                mdxinfo.encoding.name()
+            ))?;
+        keys.push((word_text, meaning_offset));
+        data = remain;
+    }
+    Ok(keys)
+}
 
+// Parse key blocks into `(remained buffer, keys)`, where `keys` is list of `(key text, record offset)`
+fn parse_mdict_keys<'a>(buf: &'a [u8], mdxinfo: &MDXInfo) -> AnyResult<(&'a [u8], Vec<(String, u64)>)> {
     log::info!("[+] Parse words ...");
     // words: Vec<(word_text: String, meaning_offset: u64)>
     let words: NomResult<Vec<(String, u64)>> = do_parse!(buf,
@@ -217,9 +400,17 @@ pub fn parse_mdx(mdxpath: &str, option: Option<ParseOption>) -> AnyResult<MDXDic
         // )
         layout: map_res!(take!(if mdxinfo.version < 20 { 16usize } else { 44usize }),
             |x: &[u8]| -> AnyResult<(u64, u64, u64, u64)> {
-                if mdxinfo.encid == 1 {
-                    return Err(elog!("words layout is encrypted by the creator"));
-                }
+                // the layout without checksum is encrypted by Salsa20/8
+                let decrypted;
+                let x = if mdxinfo.encid & 1 == 1 {
+                    let key = mdxinfo.key.as_ref()
+                        .context(elog!("words layout is encrypted by the creator"))?;
+                    let sz = if mdxinfo.version < 20 { x.len() } else { x.len() - 4 };
+                    decrypted = [&crypto::salsa20_8(key, &x[..sz])[..], &x[sz..]].concat();
+                    &decrypted[..]
+                } else {
+                    x
+                };
 
                 let layoutbuf = if mdxinfo.version < 20 {
                     x
@@ -264,7 +455,7 @@ pub fn parse_mdx(mdxpath: &str, option: Option<ParseOption>) -> AnyResult<MDXDic
                     let r: NomResult<_> = tuple!(x, le_u32, take!(4));
                     let (data, (packtype, adler32buf)) = r.context(elog!("take adler failed"))?;
 
-                    let data = if mdxinfo.encid == 2 {
+                    let data = if mdxinfo.encid & 2 == 2 {
                         let ripemed128_message = [
                             adler32buf[0], adler32buf[1], adler32buf[2], adler32buf[3],
                             0x95, 0x36, 0x00, 0x00,
@@ -294,13 +485,13 @@ pub fn parse_mdx(mdxpath: &str, option: Option<ParseOption>) -> AnyResult<MDXDic
 
                 let get_word_size = |chrcnt| -> u64 {
                     if mdxinfo.version < 20 {
-                        if mdxinfo.encoding == "UTF-16" {
+                        if mdxinfo.utf16() {
                             chrcnt * 2
                         } else {
                             chrcnt
                         }
                     } else {
-                        if mdxinfo.encoding == "UTF-16" {
+                        if mdxinfo.utf16() {
                             (chrcnt + 1) * 2
                         } else {
                             chrcnt + 1
@@ -349,56 +540,10 @@ pub fn parse_mdx(mdxpath: &str, option: Option<ParseOption>) -> AnyResult<MDXDic
                     let (_block_word_count, packsz, unpacksz) = infos[i];
                     let packet = MdxPacket::new(x, packsz).context(elog!("mdxpacket error"))?;
 
-                    let data = if packet.packtype == 1 {
-                        minilzo_rs::LZO::init().context(elog!("failed to initialize minilzo"))?
-                            .decompress_safe(&packet.data[..], unpacksz as usize)
-                            .context(elog!("lzo decompress failed"))?
-                    } else if packet.packtype == 2 {
-                        let mut data = vec![];
-                        zlib::Decoder::new(packet.data).read_to_end(&mut data)
-                            .context(elog!("zlib decoding failed"))?;
-                        let mut adler = Adler32::new();
-                        adler.write_slice(&data[..]);
-                        if adler.checksum() != packet.adler32 {
-                            return Err(elog!("wrong word block {} adler32", i));
-                        }
-                        data
-                    } else {
-                        packet.data.to_vec()
-                    };
-
-                    let mut data = &data[..];
-                    let mut subwords: Vec<(String, u64)> = vec![];
-                    loop {
-                        if data.len() == 0 {
-                            break;
-                        }
+                    let data = mdx_unpack(mdxinfo, &packet, unpacksz)
+                        .context(elog!("failed to unpack word block {}", i))?;
 
-                        let nullchar = if &mdxinfo.encoding == "UTF-16" {
-                            "\x00\x00"
-                        } else {
-                            "\x00"
-                        };
-
-                        let r: NomResult<_> = do_parse!(data,
-                            meaning_offset: take!(mdxinfo.integersz) >>
-                            word_text: take_until!(nullchar) >>
-                            _end: take!(nullchar.len()) >> (
-                                (bytes_to_u64(meaning_offset, true), word_text)
-                            )
-                        );
-
-                        let (remain, (meaning_offset, word_text)) = r
-                            .context(elog!("meaning_offset"))?;
-                        let word_text = mdx_decode(&mdxinfo, word_text)
-                            .context(elog!(
-                                "failed to decode {:x?} with encode {}",
-                                word_text,
-                                mdxinfo.encoding
-                            ))?;
-                        subwords.push((word_text, meaning_offset));
-                        data = remain;
-                    }
+                    let mut subwords = split_key_block(mdxinfo, &data[..])?;
                     log::trace!("[+] word_list[{}] contains {} words", i, subwords.len());
                     words.append(&mut subwords);
 
@@ -412,427 +557,778 @@ pub fn parse_mdx(mdxpath: &str, option: Option<ParseOption>) -> AnyResult<MDXDic
         )
     ); // words parsing
     let (buf, words) = words.context(elog!("word parsing failed"))?;
-    mdict.header.insert("WordCount".to_owned(), words.len().to_string());
     log::info!("[+] Got {} words", words.len());
 
-    log::info!("[+] Parse meanings ...");
-    let meanings: NomResult<Vec<u8>> = do_parse!(buf,
+    Ok((buf, words))
+}
+
+// Parse record block table into `(infos, blocks)`, where `infos` is list of `(packsz, unpacksz)` of
+// record blocks and `blocks` is the buffer starting from the first record block
+fn parse_record_table<'a>(buf: &'a [u8], mdxinfo: &MDXInfo) -> AnyResult<(Vec<(u64, u64)>, &'a [u8])> {
+    let table: NomResult<_> = do_parse!(buf,
         meaning_block_count: take!(mdxinfo.integersz) >>
         _word_count: take!(mdxinfo.integersz)  >>
         _meaning_info_size: take!(mdxinfo.integersz) >>
-        meaning_block_size: take!(mdxinfo.integersz) >>
-        meanings: map_res!(
-            tuple!(
-                // meanings info of vector of (packsz, unpacksz) with length of meaning_block_count
-                count!(
-                    pair!(take!(mdxinfo.integersz), take!(mdxinfo.integersz)),
-                    bytes_to_u64(meaning_block_count, true) as usize
-                ),
-                take!(bytes_to_u64(meaning_block_size, true))
-            ),
-            // Vec<(packsz, unpacksz)>, meaning_block
-            |x: (Vec<(&[u8], &[u8])>, &[u8])| -> AnyResult<Vec<u8>> {
-                let (infos, mut meaningsbuf) = x;
-                let mut meanings: Vec<u8> = vec![];
-                for (packsz, unpacksz) in infos {
-                    let packsz = bytes_to_u64(packsz, true);
-                    let unpacksz = bytes_to_u64(unpacksz, true);
-                    let packet = MdxPacket::new(meaningsbuf, packsz as u64)
-                        .context(elog!("failed to create MdxPacket"))?;
-                    let mut unpackbuf = if mdxinfo.version < 20 {
-                        if packet.packtype != 0 {
-                            minilzo_rs::LZO::init()
-                                .context(elog!("failed to initialize lzo"))?
-                                .decompress_safe(&packet.data[..], unpacksz as usize)
-                                .context(elog!("meanings lzo decompress failed"))?
-                        } else {
-                            packet.data.to_vec()
-                        }
-                    } else {
-                        let unpackbuf = if packet.packtype != 0 {
-                            let mut unpackbuf: Vec<u8> = Vec::new();
-                            zlib::Decoder::new(packet.data)
-                                .read_to_end(&mut unpackbuf)
-                                .context(elog!("zlib decoding failed"))?;
-                            unpackbuf
-                        } else {
-                            packet.data.to_vec()
-                        }
-                         ;
-                        let mut adler = Adler32::new();
-                        adler.write_slice(&unpackbuf[..]);
-                        if adler.checksum() != packet.adler32 {
-                            return Err(elog!("[x] wrong adler32 for meaning block"));
-                        }
-                        unpackbuf
-                    };
-                    meanings.append(&mut unpackbuf);
-                    meaningsbuf = packet.remain;
-                }
-                Ok(meanings)
-            }
+        _meaning_block_size: take!(mdxinfo.integersz) >>
+        // meanings info of vector of (packsz, unpacksz) with length of meaning_block_count
+        infos: count!(
+            pair!(take!(mdxinfo.integersz), take!(mdxinfo.integersz)),
+            bytes_to_u64(meaning_block_count, true) as usize
         ) >>
         (
-            meanings
+            infos
         )
     );
-    // ignore the remained buffer
-    let (_, meanings) = meanings.context(elog!("failed to parse meaning "))?;
+    let (blocks, infos) = table.context(elog!("failed to parse meaning table"))?;
+    let infos = infos.into_iter()
+        .map(|(packsz, unpacksz)| (bytes_to_u64(packsz, true), bytes_to_u64(unpacksz, true)))
+        .collect();
+    Ok((infos, blocks))
+}
+
+// parse_mdx will parse mdx file into list of (word, meaning) pair, all words are space trimed and
+// converted into lowercase.
+//
+// All entries are kept in memory, use [MdxReader::entries] to iterate them instead for large mdx.
+pub fn parse_mdx(mdxpath: &str, option: Option<ParseOption>) -> AnyResult<MDXDict> {
+    let mut mdict = MDXDict::default();
+
+    if let Some(ParseOption::OnlyHeader) = option {
+        let file = File::open(mdxpath).context(elog!("failed to open {}", mdxpath))?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        let (_, meta) = parse_mdict_header(&mmap[..])?;
+        mdict.header = meta;
+        return Ok(mdict);
+    }
+
+    let reader = MdxReader::open(mdxpath)?;
+    mdict.header = reader.header.clone();
+    mdict.header.insert("WordCount".to_owned(), reader.len().to_string());
 
-    let mut word_meaning_list: Vec<(String, String)> = vec![];
     log::info!("[+] Combine words and meanings ...");
-    let wordcnt = words.len();
-    let bar = ProgressBar::new(wordcnt as u64);
-    for i in 0..wordcnt {
+    let bar = ProgressBar::new(reader.len() as u64);
+    for entry in reader.entries() {
         bar.inc(1);
-        let (start, word) = (words[i].1 as usize, words[i].0.clone());
-        let end = if i == 0 {
-            // the first element
-            if words.len() == 1 {
-                meanings.len()
-            } else {
-                words[1].1 as usize
-            }
-        } else if i == words.len() - 1{
-            // the last element
-            meanings.len()
-        } else {
-            // middle element
-            words[i + 1].1 as usize
-        };
-        let meaning = mdx_decode(&mdxinfo, &meanings[start..end])
-            .context(elog!(
-                "failed to decode meaning {:x?} with encode {}",
-                &meanings[start..end],
-                mdxinfo.encoding
-            ))?;
-        word_meaning_list.push((util::normalize_word(word), meaning));
+        mdict.entries.push(entry?);
     }
     bar.finish_with_message("Parsing MDX is done!");
-    mdict.entries = word_meaning_list;
 
     Ok(mdict)
 }
 
-pub fn create_mdx<P: AsRef<Path>>(title: &str, author: &str, description: &str, srcpath: P, dstpath: P) -> AnyResult<()> {
-    let dstpath = dstpath.as_ref();
-    let mut dstmdx = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(dstpath)
-        .context(elog!("Cannot open {:?}", dstpath.display()))?;
+/// Parse mdd file into list of `(resource path, resource content)`
+///
+/// Resource paths are kept as they are stored in mdd, which are usually windows style paths such
+/// as `\\img\\cat.png`, see [util::normalize_resource_path] to normalize them.
+///
+/// All resources are kept in memory, use [MddReader::resources] to iterate them instead for large
+/// mdd.
+pub fn parse_mdd(mddpath: &str) -> AnyResult<Vec<(String, Vec<u8>)>> {
+    let reader = MddReader::open(mddpath)?;
+    log::info!("[+] Got {} resources", reader.len());
+    reader.resources().collect()
+}
 
-    log::info!("[+] Write mdx header ...");
+/// Reader of mdx which decodes records one block at a time
+///
+/// The file is memory mapped and only its header and keys are parsed when it is opened, so that
+/// large dictionary can be converted with bounded memory by [MdxReader::entries].
+#[derive(Debug)]
+pub struct MdxReader {
+    pub header: HashMap<String, String>,
+    info: MDXInfo,
+    mmap: Mmap,
+    // `(word, record offset)` list in the order of records
+    keys: Vec<(String, u64)>,
+    blocks: Vec<RecordBlock>,
+}
+
+#[derive(Debug)]
+struct RecordBlock {
+    // offset of block in file
+    offset: usize,
+    packsz: u64,
+    // offset of the first record in the concatenation of uncompressed blocks
+    start: u64,
+    unpacksz: u64,
+}
+
+impl MdxReader {
+    pub fn open<P>(path: P) -> AnyResult<Self> where P: AsRef<Path> {
+        Self::open_mdict(path.as_ref(), false)
+    }
+
+    // Open mdx or mdd, the keys of mdd are resource paths
+    fn open_mdict(path: &Path, mdd: bool) -> AnyResult<Self> {
+        let file = File::open(path).context(elog!("failed to open {}", path.display()))?;
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        log::info!("[+] Parse header ...");
+        let (buf, header) = parse_mdict_header(&mmap[..])?;
+        log::info!("[+] Got header\n{:#x?}", header);
+        let mut info = MDXInfo::new(&header)?;
+        if mdd {
+            // resource paths are always encoded by UTF-16 whatever the encoding of mdx is
+            info.encoding = UTF_16LE;
+        } else {
+            info.unlock(path)?;
+        }
+        let (keys, mut blocks) = parse_mdict_index(buf, &info)
+            .context(elog!("failed to parse {}", path.display()))?;
+        let headersz = mmap.len() - buf.len();
+        for block in blocks.iter_mut() {
+            block.offset += headersz;
+        }
+        Ok(Self { header, info, mmap, keys, blocks })
+    }
+
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Name of text encoding such as `UTF-8`
+    pub fn encoding(&self) -> &'static str {
+        self.info.encoding.name()
+    }
+
+    /// Value of `Encrypted` header, bit 0 means the words layout is encrypted by registration code
+    /// and bit 1 means the key block infos are encrypted
+    pub fn encryption(&self) -> u32 {
+        self.info.encid
+    }
+
+    /// Number of record blocks
+    pub fn record_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Iterate `(word, meaning)` pairs in the order of records, only one uncompressed record
+    /// block is kept in memory
+    pub fn entries(&self) -> MdxEntries<'_> {
+        MdxEntries { reader: self, pos: 0, cache: MdxBlockCache::new(1) }
+    }
+
+    /// Read meaning of the `i`-th entry in the order of [MdxReader::entries], uncompressed record
+    /// blocks are kept in `cache` for the next reads
+    pub fn record(&self, i: usize, cache: &mut MdxBlockCache) -> AnyResult<String> {
+        if i >= self.keys.len() {
+            return Err(elog!("record {} is out of range", i));
+        }
+        self.read_record(i, cache)
+    }
+
+    // Read record of the `i`-th key and keep its uncompressed block in `cache`
+    fn read_record(&self, i: usize, cache: &mut MdxBlockCache) -> AnyResult<String> {
+        let buf = self.record_bytes(i, cache)?;
+        mdx_decode(&self.info, buf)
+            .context(elog!("failed to decode meaning with encode {}", self.info.encoding.name()))
+    }
+
+    // Get raw bytes of record of the `i`-th key in its uncompressed block kept in `cache`
+    fn record_bytes<'c>(&self, i: usize, cache: &'c mut MdxBlockCache) -> AnyResult<&'c [u8]> {
+        let recordsz = self.blocks.last().map(|x| x.start + x.unpacksz).unwrap_or(0);
+        let (start, end) = record_range(&self.keys, i, recordsz as usize)?;
+        let pos = self.blocks.partition_point(|x| x.start + x.unpacksz <= start as u64);
+        let block = self.blocks.get(pos).context(elog!("record of {} is out of range", self.keys[i].0))?;
+        let data = cache.get_or_load(pos, || {
+            let packet = MdxPacket::new(&self.mmap[block.offset..], block.packsz)
+                .context(elog!("failed to create MdxPacket"))?;
+            mdx_unpack(&self.info, &packet, block.unpacksz)
+                .context(elog!("failed to unpack record block {}", pos))
+        })?;
+        // a record never crosses blocks
+        let (start, end) = (start - block.start as usize, (end - block.start as usize).min(data.len()));
+        data.get(start..end).context(elog!("record of {} is out of range", self.keys[i].0))
+    }
+}
+
+/// Reader of mdd which decodes resources one block at a time like [MdxReader]
+#[derive(Debug)]
+pub struct MddReader {
+    reader: MdxReader,
+}
+
+impl MddReader {
+    pub fn open<P>(path: P) -> AnyResult<Self> where P: AsRef<Path> {
+        Ok(Self { reader: MdxReader::open_mdict(path.as_ref(), true)? })
+    }
+
+    /// Number of resources
+    pub fn len(&self) -> usize {
+        self.reader.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reader.is_empty()
+    }
+
+    /// Resource paths in the order of [MddReader::resources], they are kept as they are stored in
+    /// mdd, see [parse_mdd]
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.reader.keys.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Read content of the `i`-th resource, uncompressed record blocks are kept in `cache` for
+    /// the next reads
+    pub fn resource(&self, i: usize, cache: &mut MdxBlockCache) -> AnyResult<Vec<u8>> {
+        if i >= self.reader.keys.len() {
+            return Err(elog!("resource {} is out of range", i));
+        }
+        Ok(self.reader.record_bytes(i, cache)?.to_vec())
+    }
+
+    /// Iterate `(resource path, resource content)` pairs in the order of records, only one
+    /// uncompressed record block is kept in memory
+    pub fn resources(&self) -> impl Iterator<Item = AnyResult<(String, Vec<u8>)>> + '_ {
+        let mut cache = MdxBlockCache::new(1);
+        self.reader.keys.iter().enumerate().map(move |(i, (name, _))| {
+            Ok((name.clone(), self.reader.record_bytes(i, &mut cache)?.to_vec()))
+        })
+    }
+}
+
+/// Uncompressed record blocks of [MdxReader], the least recently used one is dropped when it
+/// is full
+pub struct MdxBlockCache {
+    // `(block position, block)` list, the most recently used one is at back
+    blocks: VecDeque<(usize, Vec<u8>)>,
+    capacity: usize,
+}
+
+impl MdxBlockCache {
+    /// Cache keeping at most `capacity` blocks
+    pub fn new(capacity: usize) -> Self {
+        Self { blocks: VecDeque::new(), capacity: capacity.max(1) }
+    }
+
+    fn get_or_load<F>(&mut self, pos: usize, load: F) -> AnyResult<&[u8]>
+        where F: FnOnce() -> AnyResult<Vec<u8>>
+    {
+        match self.blocks.iter().position(|x| x.0 == pos) {
+            Some(i) => {
+                let block = self.blocks.remove(i).context(elog!("block {} is not cached", pos))?;
+                self.blocks.push_back(block);
+            },
+            None => {
+                let data = load()?;
+                if self.blocks.len() >= self.capacity {
+                    self.blocks.pop_front();
+                }
+                self.blocks.push_back((pos, data));
+            }
+        }
+        Ok(self.blocks.back().map(|x| &x.1[..]).unwrap_or_default())
+    }
+}
+
+/// Iterator of `(word, meaning)` pairs returned by [MdxReader::entries]
+pub struct MdxEntries<'a> {
+    reader: &'a MdxReader,
+    pos: usize,
+    cache: MdxBlockCache,
+}
+
+impl<'a> Iterator for MdxEntries<'a> {
+    type Item = AnyResult<(String, String)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let (word, _) = self.reader.keys.get(self.pos)?;
+        let entry = self.reader.read_record(self.pos, &mut self.cache)
+            .map(|meaning| (util::trim_word(word), meaning));
+        self.pos += 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remain = self.reader.len() - self.pos;
+        (remain, Some(remain))
+    }
+}
+
+/// MDX dictionary which is looked up in place
+///
+/// Only the header and keys are parsed when it is loaded, and the record block containing the
+/// requested word is decompressed on lookup. Loading builds index of all keys, so a dictionary
+/// should be loaded once and shared, which is what [crate::cache::load_dictionary] does for the
+/// server. Style and script are read from `x.css` and `x.js` alongside with `x.mdx` if they
+/// exist. Like [crate::wikit::LocalDictionary], it is (de)serialized as its path.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "PathBuf", into = "PathBuf")]
+pub struct MdxDictionary {
+    pub header: HashMap<String, String>,
+    // local path of dictionary
+    pub path: PathBuf,
+    pub style: String,
+    pub script: String,
+    reader: Arc<MdxReader>,
+    // positions in keys of words sharing the same index key
+    groups: Arc<Vec<Vec<usize>>>,
+    // index key => position in `groups`
+    idx: index::FSTIndex,
+}
+
+impl TryFrom<PathBuf> for MdxDictionary {
+    type Error = anyhow::Error;
+    fn try_from(path: PathBuf) -> AnyResult<Self> {
+        Self::load(path)
+    }
+}
+
+impl From<MdxDictionary> for PathBuf {
+    fn from(dict: MdxDictionary) -> Self {
+        dict.path
+    }
+}
+
+impl MdxDictionary {
+    pub fn load<P>(path: P) -> AnyResult<Self> where P: AsRef<Path> {
+        let path = path.as_ref();
+        let reader = MdxReader::open(path)?;
+
+        let norm = index::KeyNormalization::NfkcFold;
+        let mut groups: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (i, (word, _)) in reader.keys.iter().enumerate() {
+            groups.entry(norm.normalize(word)).or_default().push(i);
+        }
+        let table = groups.keys().enumerate().map(|(i, key)| (key, i as u64)).collect::<Vec<_>>();
+        let idx = index::FSTIndex::build(&mut table.iter(), norm)?;
+
+        let read_alongside = |ext: &str| std::fs::read_to_string(path.with_extension(ext)).unwrap_or_default();
+        Ok(Self {
+            style: read_alongside("css"),
+            script: read_alongside("js"),
+            header: reader.header.clone(),
+            path: path.to_path_buf(),
+            reader: Arc::new(reader),
+            groups: Arc::new(groups.into_values().collect()),
+            idx,
+        })
+    }
+
+    /// Get title of dictionary, the file name is used if it has no title
+    pub fn name(&self) -> String {
+        match self.header.get("Title").map(|x| x.trim()) {
+            Some(title) if title.len() > 0 => title.to_string(),
+            _ => self.path.file_stem().and_then(|x| x.to_str()).unwrap_or_default().to_string(),
+        }
+    }
+
+    /// Lookup `word` and its similar words, see [index::LookupOptions] for the available options
+    pub fn lookup<P>(&self, word: P, options: &index::LookupOptions) -> AnyResult<Vec<(String, String)>> where P: AsRef<str> {
+        self.read_groups(self.idx.lookup(word, options)?)
+    }
+
+    /// Get at most `limit` `(word, meaning)` pairs whose word starts with `prefix`
+    pub fn prefix<P>(&self, prefix: P, limit: usize) -> AnyResult<Vec<(String, String)>> where P: AsRef<str> {
+        self.read_groups(self.idx.prefix(prefix, limit)?)
+    }
+
+    /// Get at most `limit` `(word, meaning)` pairs whose word matches `pattern`
+    pub fn pattern<P>(&self, pattern: P, syntax: index::PatternSyntax, limit: usize) -> AnyResult<Vec<(String, String)>> where P: AsRef<str> {
+        self.read_groups(self.idx.pattern(pattern, syntax, limit)?)
+    }
+
+    pub fn get_script(&self) -> &str {
+        &self.script
+    }
+
+    pub fn get_style(&self) -> &str {
+        &self.style
+    }
+
+    // Read `(word, meaning)` list of `(index key, group position)` list, redirect entries are
+    // followed
+    fn read_groups(&self, poslist: Vec<(String, u64)>) -> AnyResult<Vec<(String, String)>> {
+        let mut cache = MdxBlockCache::new(1);
+        let mut anslist = vec![];
+        for (_, pos) in poslist {
+            let group = self.groups.get(pos as usize).context(elog!("index is broken"))?;
+            for i in group.iter() {
+                let meaning = self.reader.read_record(*i, &mut cache)?;
+                let word = util::trim_word(&self.reader.keys[*i].0);
+                let lookup = |target: &str| -> Option<(String, String)> {
+                    let pos = self.idx.get(target).ok()??;
+                    let group = self.groups.get(pos as usize)?;
+                    let i = group.iter().find(|i| self.reader.keys[**i].0.trim() == target).or(group.first())?;
+                    let meaning = self.reader.read_record(*i, &mut MdxBlockCache::new(1)).ok()?;
+                    Some((util::trim_word(&self.reader.keys[*i].0), meaning))
+                };
+                match util::follow_links(word.clone(), meaning.clone(), lookup) {
+                    Ok(resolved) => anslist.push(resolved),
+                    Err(e) => {
+                        log::warn!("failed to follow link of {}: {}", word, e);
+                        anslist.push((word, meaning));
+                    }
+                }
+            }
+        }
+        Ok(anslist)
+    }
+}
+
+/// Compression of key and record blocks in mdx or mdd, the value is the pack type of block
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[repr(u32)]
+pub enum MdxCompression {
+    None = 0,
+    Lzo = 1,
+    Zlib = 2,
+}
+
+impl MdxCompression {
+    pub fn from_name<S>(name: S) -> Option<Self> where S: AsRef<str> {
+        match name.as_ref().trim().to_lowercase().as_str() {
+            "" | "none" => Some(MdxCompression::None),
+            "lzo" => Some(MdxCompression::Lzo),
+            "zlib" => Some(MdxCompression::Zlib),
+            _ => None,
+        }
+    }
+}
+
+/// Text encoding of mdx, UTF-16 and GB18030 are used by old mdict readers
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MdxEncoding {
+    Utf8,
+    Utf16,
+    Gb18030,
+}
+
+impl MdxEncoding {
+    pub fn from_name<S>(name: S) -> Option<Self> where S: AsRef<str> {
+        match name.as_ref().trim().to_lowercase().as_str() {
+            "" | "utf8" | "utf-8" => Some(MdxEncoding::Utf8),
+            "utf16" | "utf-16" => Some(MdxEncoding::Utf16),
+            "gb18030" => Some(MdxEncoding::Gb18030),
+            _ => None,
+        }
+    }
+
+    // Encoding label in mdx header
+    fn label(&self) -> &'static str {
+        match self {
+            MdxEncoding::Utf8 => "UTF-8",
+            MdxEncoding::Utf16 => "UTF-16",
+            MdxEncoding::Gb18030 => "GB18030",
+        }
+    }
+
+    // UTF-16 is always little endian in mdx, note that encoder of `encoding_rs` never outputs UTF-16
+    fn encode(&self, text: &str) -> Vec<u8> {
+        match self {
+            MdxEncoding::Utf8 => text.as_bytes().to_vec(),
+            MdxEncoding::Utf16 => text.encode_utf16().flat_map(|x| x.to_le_bytes()).collect(),
+            MdxEncoding::Gb18030 => GB18030.encode(text).0.into_owned(),
+        }
+    }
+}
+
+/// Options to write mdx or mdd file
+///
+/// Blocks are stored without compression by default, which is readable by all mdict clients. Old
+/// clients may not understand zlib, and LZO gives larger file than zlib.
+#[derive(Debug, Clone)]
+pub struct MdxWriteOptions {
+    /// Maximum size of uncompressed key block or record block, a block always holds at least one
+    /// item even if the item is larger than this size
+    pub block_size: usize,
+    pub compression: MdxCompression,
+    /// Compression level from 0 (fastest) to 9 (smallest), which is only used by zlib
+    pub level: u32,
+    /// Encoding of words and meanings, resource paths of mdd are always encoded by UTF-16
+    pub encoding: MdxEncoding,
+}
+
+impl Default for MdxWriteOptions {
+    fn default() -> Self {
+        Self {
+            block_size: MAX_MDX_ITEM_SIZE,
+            compression: MdxCompression::None,
+            level: 6,
+            encoding: MdxEncoding::Utf8,
+        }
+    }
+}
+
+/// Parse style sheet of mdx into map from style number to `(begin tag, end tag)`
+///
+/// Each style takes three lines: number, begin tag and end tag, where the tags may be empty.
+pub fn parse_stylesheet(stylesheet: &str) -> HashMap<String, (String, String)> {
+    let lines = stylesheet.lines().collect::<Vec<_>>();
+    let mut styles = HashMap::new();
+    for style in lines.chunks(3) {
+        let tag = |i: usize| style.get(i).map(|x| x.to_string()).unwrap_or_default();
+        styles.insert(style[0].trim().to_string(), (tag(1), tag(2)));
+    }
+    styles
+}
+
+/// Expand text marked by `` `n` `` in `meaning` with tags of style `n`, the style lasts until next
+/// mark or end of the meaning
+pub fn apply_stylesheet(meaning: &str, styles: &HashMap<String, (String, String)>) -> String {
+    static STYLE_MARK: Lazy<regex::Regex> = Lazy::new(|| regex::Regex::new(r"`(\d+)`").unwrap());
+    if styles.is_empty() {
+        return meaning.to_string();
+    }
+    let (mut expanded, mut last, mut end) = (String::new(), 0, None);
+    for mark in STYLE_MARK.captures_iter(meaning) {
+        let (start, stop) = mark.get(0).map(|x| (x.start(), x.end())).unwrap_or_default();
+        expanded.push_str(&meaning[last..start]);
+        if let Some(end) = end.take() {
+            expanded.push_str(end);
+        }
+        match styles.get(&mark[1]) {
+            Some((begin, style_end)) => {
+                expanded.push_str(begin);
+                end = Some(style_end.as_str());
+            },
+            None => expanded.push_str(&meaning[start..stop]),
+        }
+        last = stop;
+    }
+    expanded.push_str(&meaning[last..]);
+    if let Some(end) = end {
+        expanded.push_str(end);
+    }
+    expanded
+}
+
+/// Create mdx file from text source `srcpath` with header taken from `profile`
+///
+/// The style and script of profile are written alongside with `dstpath` as `x.css` and `x.js`,
+/// which are loaded by mdict clients.
+pub fn create_mdx<P: AsRef<Path>>(profile: &WikitDictProfile, srcpath: P, dstpath: P, options: &MdxWriteOptions) -> AnyResult<()> {
+    let author = profile.author();
+    let stylesheet = profile.stylesheet();
     let mut meta = HashMap::new();
     meta.insert("GeneratedByEngineVersion", "2.0");
     meta.insert("RequiredEngineVersion", "2.0");
     meta.insert("Encrypted", "0");
-    meta.insert("Encoding", "UTF-8");
+    meta.insert("Encoding", options.encoding.label());
     meta.insert("Format", "Html");
     let now: DateTime<Local> = Local::now();
     let create_date = now.format("%Y-%m-%d %H:%M:%S").to_string();
     meta.insert("CreationDate", &create_date);
     meta.insert("Compact", "No");
     meta.insert("Compat", "No");
-    meta.insert("KeyCaseSensitive", "No");
-    meta.insert("Description", description);
-    meta.insert("Title", title);
+    meta.insert("KeyCaseSensitive", if profile.key_case_sensitive() { "Yes" } else { "No" });
+    meta.insert("Description", profile.description());
+    meta.insert("Title", profile.name());
+    meta.insert("Author", &author);
     meta.insert("DataSourceFormat", "106");
-    meta.insert("StyleSheet", "");
-    meta.insert("RegisterBy", author);
+    meta.insert("StyleSheet", &stylesheet);
+    // `RegisterBy` (`EMail` or `DeviceID`) tells clients how the dictionary is registered, which is
+    // empty since the dictionary is not encrypted
+    meta.insert("RegisterBy", "");
     // For the offical Mdict dictionary, field `RegCode` should remain empty
     meta.insert("RegCode", "");
 
-    // Convert meta map to string and encoded as UTF16-LE
-    let mut metastr = format!("<Dictionary ");
-    for (k, v) in meta.iter() {
-        metastr.push_str(format!("{}=\"{}\" ", k, v).as_str());
-    }
-    metastr.push_str("/>\r\n\x00");
-    let mut metabytes = vec![];
-    for ch in metastr.encode_utf16() {
-        let bytes = ch.to_le_bytes();
-        metabytes.push(bytes[0]);
-        metabytes.push(bytes[1]);
-    }
-
-    let metasz = metabytes.len() as u32;
-    let mut adler = Adler32::new();
-    adler.write_slice(&metabytes[..]);
-    let adler32 = adler.checksum() as u32;
-    dstmdx.write(&metasz.to_be_bytes()[..])?;
-    for ch in metabytes {
-        dstmdx.write(&ch.to_be_bytes()[..])?;
-    }
-    dstmdx.write(&adler32.to_le_bytes()[..])?;
-
     // Read MDX source file and sort (word, meaning) by word
     let path = srcpath.as_ref();
     let file = File::open(path).context(elog!("Cannot open {:?}", path.display()))?;
     let mdxsrc = MDXSource::new(file);
-    let mut mdxitems: Vec<_> = mdxsrc.collect();
-    mdxitems.sort_by_key(|k| k.clone().0);
-    // Build offset table which is used to build block
-    #[derive(Debug)]
-    struct OffsetTable<'a> {
-        offset: u64,
-        word_text: &'a str,
-        word: &'a [u8],
-        meaning: &'a [u8],
-        meaning_text: &'a str,
-    }
-    #[derive(Debug)]
-    struct OffsetTables<'a> {
-        used_for_word: bool,
-        counter: usize,
-        entries: Vec<OffsetTable<'a>>,
-    }
-    #[derive(Debug)]
-    struct WordInfoEntry {
-        block_word_count: u64,
-        first_word_size: u16,
-        first_word: Vec<u8>,
-        last_word_size: u16,
-        last_word: Vec<u8>,
-        packsz: u64,
-        unpacksz: u64,
-    }
-    #[derive(Debug)]
-    struct MeaningInfoEntry {
-        packsz: u64,
-        unpacksz: u64,
-    }
-    #[derive(Debug)]
-    enum InfoEntry {
-        WordInfoEntry(WordInfoEntry),
-        MeaningInfoEntry(MeaningInfoEntry),
-    }
-    #[derive(Debug)]
-    struct ValueEntry {
-        packtype: u32,
-        adler32: u32,
-        data: Vec<u8>,
-    }
-    impl<'a> Iterator for OffsetTables<'a> {
-        // Use dyn trait is not a good idea, see https://bennetthardwick.com/blog/dont-use-boxed-trait-objects-for-struct-internals/
-        type Item = (InfoEntry, ValueEntry);
-        fn next(&mut self) -> Option<Self::Item> {
-            let (begidx, mut endidx, mut block_size) = (self.counter as usize, 0usize, 0u32);
-            let mut reach_end = true;
-            for (i, offtbl) in self.entries.iter().skip(self.counter).enumerate() {
-                let itemsz = if self.used_for_word {
-                    offtbl.word.len() as u32
-                } else {
-                    offtbl.meaning.len() as u32
-                };
+    let mut mdxitems: Vec<(String, String)> = mdxsrc.collect();
+    // clients search keys case-insensitively unless `KeyCaseSensitive` is `Yes`, so keys are
+    // sorted by lowercase
+    if profile.key_case_sensitive() {
+        mdxitems.sort_by(|a, b| a.0.cmp(&b.0));
+    } else {
+        mdxitems.sort_by(|a, b| (a.0.to_lowercase(), &a.0).cmp(&(b.0.to_lowercase(), &b.0)));
+    }
+    let meanings = mdxitems.iter().map(|(_, meaning)| options.encoding.encode(meaning)).collect::<Vec<_>>();
+    let items = mdxitems.iter().zip(meanings.iter())
+        .map(|((word, _), meaning)| (options.encoding.encode(word), &meaning[..]))
+        .collect::<Vec<_>>();
 
-                if block_size + itemsz > MAX_MDX_ITEM_SIZE as u32 {
-                    endidx = self.counter + i;
-                    reach_end = false;
-                    break;
-                }
+    write_mdict(dstpath.as_ref(), "Dictionary", &meta, &items, options)?;
 
-                if begidx >= endidx {
-                    block_size += itemsz;
-                }
-            }
-            if reach_end {
-                endidx = self.entries.len();
+    let dstpath = dstpath.as_ref();
+    for (content, suffix) in [(profile.style(), "css"), (profile.script(), "js")] {
+        if content.trim().len() > 0 {
+            let path = dstpath.with_extension(suffix);
+            std::fs::write(&path, content).context(elog!("failed to write {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Create mdd file from resources in directory `srcdir`
+///
+/// The key of resource is its path relative to `srcdir` separated by backslash such as
+/// `\\img\\cat.png`, which is used by mdx to refer the resource.
+pub fn create_mdd<P: AsRef<Path>>(srcdir: P, dstpath: P) -> AnyResult<()> {
+    let srcdir = srcdir.as_ref();
+    if !srcdir.is_dir() {
+        return Err(elog!("{} is not a directory", srcdir.display()));
+    }
+    let mut resources = vec![];
+    let mut dirs = vec![srcdir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).context(elog!("cannot read directory {}", dir.display()))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
             }
+            let name = path.strip_prefix(srcdir)?.to_str()
+                .context(elog!("invalid resource path {}", path.display()))?;
+            let key = format!("\\{}", util::normalize_resource_path(name).replace('/', "\\"));
+            let mut buf = vec![];
+            File::open(&path).context(elog!("Cannot open {:?}", path.display()))?
+                .read_to_end(&mut buf)?;
+            resources.push((key, buf));
+        }
+    }
+    // keys are compared without case by mdict
+    resources.sort_by(|a, b| (a.0.to_lowercase(), &a.0).cmp(&(b.0.to_lowercase(), &b.0)));
+    log::info!("[+] Got {} resources", resources.len());
 
-            if begidx < endidx {
-                let mut value_entry = ValueEntry {
-                    // no compression
-                    packtype: 0u32,
-                    adler32: 0u32,
-                    data: vec![],
-                };
-                let mut rawdata = vec![];
-                for j in begidx..endidx {
-                    let data = if self.used_for_word {
-                        // meaning_offset and word_text
-                        let mut v = self.entries[j].offset.to_be_bytes().to_vec();
-                        v.append(&mut self.entries[j].word.to_vec());
-                        v
-                    } else {
-                        // meaning_segment
-                        let v = self.entries[j].meaning.to_vec();
-                        v
-                    };
-                    rawdata.extend(data);
-                }
-                let unpacksz = rawdata.len();
-                let packsz = rawdata.len() + 8;
-                value_entry.data.extend(rawdata);
-                value_entry.adler32 = {
-                    let mut adler = Adler32::new();
-                    adler.write_slice(&value_entry.data[..]);
-                    adler.checksum()
-                };
+    let mut meta = HashMap::new();
+    meta.insert("GeneratedByEngineVersion", "2.0");
+    meta.insert("RequiredEngineVersion", "2.0");
+    meta.insert("Encrypted", "0");
+    meta.insert("Encoding", "");
+    meta.insert("Format", "");
+    let now: DateTime<Local> = Local::now();
+    let create_date = now.format("%Y-%m-%d %H:%M:%S").to_string();
+    meta.insert("CreationDate", &create_date);
+    meta.insert("Compact", "No");
+    meta.insert("Compat", "No");
+    meta.insert("KeyCaseSensitive", "No");
+    meta.insert("Description", "");
+    meta.insert("Title", "");
+    meta.insert("DataSourceFormat", "106");
+    meta.insert("StyleSheet", "");
+    meta.insert("RegisterBy", "");
+    meta.insert("RegCode", "");
 
-                let entry: InfoEntry = if self.used_for_word {
-                    InfoEntry::WordInfoEntry(WordInfoEntry {
-                        block_word_count: (endidx - begidx) as u64,
-                        first_word_size: self.entries[begidx].word.len() as u16,
-                        first_word: {
-                            let mut v = self.entries[begidx].word.to_vec();
-                            v.push(0x00);
-                            v
-                        },
-                        last_word_size: self.entries[endidx - 1].word.len() as u16,
-                        last_word: {
-                            let mut v = self.entries[endidx - 1].word.to_vec();
-                            v.push(0x00);
-                            v
-                        },
-                        packsz: packsz as u64,
-                        unpacksz: unpacksz as u64,
-                    })
-                } else {
-                    InfoEntry::MeaningInfoEntry(MeaningInfoEntry {
-                        packsz: packsz as u64,
-                        unpacksz: unpacksz as u64,
-                    })
-                };
-                self.counter = endidx;
-                return Some((entry, value_entry));
-            } else {
-                self.counter = self.entries.len();
-                return None;
-            }
-        } // next
+    let options = MdxWriteOptions { encoding: MdxEncoding::Utf16, ..Default::default() };
+    let items = resources.iter().map(|(key, buf)| (options.encoding.encode(key), &buf[..])).collect::<Vec<_>>();
+    write_mdict(dstpath.as_ref(), "Library_Data", &meta, &items, &options)
+}
+
+// Write mdx or mdd file of version 2.0, where `tag` is the tag of header (`Dictionary` for mdx
+// and `Library_Data` for mdd), `items` is list of `(encoded key, record)` sorted by key and keys
+// are encoded by `options.encoding`.
+fn write_mdict(dstpath: &Path, tag: &str, meta: &HashMap<&str, &str>, items: &[(Vec<u8>, &[u8])], options: &MdxWriteOptions) -> AnyResult<()> {
+    let file = File::create(dstpath).context(elog!("Cannot open {:?}", dstpath.display()))?;
+    let mut dstmdx = BufWriter::new(file);
+
+    log::info!("[+] Write mdx header ...");
+    // Convert meta map to string and encoded as UTF16-LE
+    let mut metastr = format!("<{} ", tag);
+    for (k, v) in meta.iter() {
+        metastr.push_str(format!("{}=\"{}\" ", k, escape_header_value(v)).as_str());
     }
-    let mut word_count = 0u64;
-    let mut offtbls = OffsetTables { used_for_word: true, counter: 0usize, entries: vec![] };
-    let mut offset = 0u64;
-    for item in mdxitems.iter() {
-        offtbls.entries.push(OffsetTable {
-            offset: offset,
-            word_text: &item.0,
-            word: item.0.as_bytes(),
-            meaning: item.1.as_bytes(),
-            meaning_text: &item.1,
-        });
-        word_count += 1;
-        offset = offset + item.1.as_bytes().len() as u64;
-    }
-
-    enum MDXLayer {
-        WordsInfo,
-        MeaningsInfo,
-        WordsValue,
-        MeaningsValue
-    }
-    let write_mdx_layer = |file: &mut File, offtbls: &mut OffsetTables, layer: MDXLayer| -> AnyResult<(u64, u64)> {
-        offtbls.counter = 0;
-        match layer {
-            MDXLayer::WordsInfo | MDXLayer::WordsValue => {
-                offtbls.used_for_word = true;
-            },
-            MDXLayer::MeaningsInfo | MDXLayer::MeaningsValue => {
-                offtbls.used_for_word = false;
+    metastr.push_str("/>\r\n\x00");
+    let metabytes = metastr.encode_utf16().flat_map(|x| x.to_le_bytes()).collect::<Vec<u8>>();
+    let mut adler = Adler32::new();
+    adler.write_slice(&metabytes[..]);
+    dstmdx.write_all(&(metabytes.len() as u32).to_be_bytes()[..])?;
+    dstmdx.write_all(&metabytes[..])?;
+    dstmdx.write_all(&adler.checksum().to_le_bytes()[..])?;
+
+    // Split items into blocks whose uncompressed size does not exceed `block_size` unless the block
+    // contains only one item
+    let split_blocks = |sizes: &mut dyn Iterator<Item=usize>| -> Vec<std::ops::Range<usize>> {
+        let (mut blocks, mut start, mut size) = (vec![], 0usize, 0usize);
+        for (i, itemsz) in sizes.enumerate() {
+            if i > start && size + itemsz > options.block_size {
+                blocks.push(start..i);
+                start = i;
+                size = 0;
             }
+            size += itemsz;
         }
-        let (mut block_count, mut written_size) = (0u64, 0u64);
-        while let Some(item) = offtbls.next() {
-            block_count += 1;
-            match layer {
-                MDXLayer::WordsInfo | MDXLayer::MeaningsInfo => {
-                    match item.0 {
-                        InfoEntry::WordInfoEntry(info) => {
-                            written_size += 8 + 2 + (info.first_word_size + 1) as u64 + 2 + (info.last_word_size + 1) as u64 + 8 + 8;
-                            file.write(&info.block_word_count.to_be_bytes()[..])?;
-                            file.write(&info.first_word_size.to_be_bytes()[..])?;
-                            file.write(&info.first_word[..])?;
-                            file.write(&info.last_word_size.to_be_bytes()[..])?;
-                            file.write(&info.last_word[..])?;
-                            file.write(&info.packsz.to_be_bytes()[..])?;
-                            file.write(&info.unpacksz.to_be_bytes()[..])?;
-                        },
-                        InfoEntry::MeaningInfoEntry(info) => {
-                            written_size += 8 + 8;
-                            file.write(&info.packsz.to_be_bytes()[..])?;
-                            file.write(&info.unpacksz.to_be_bytes()[..])?;
-                        }
-                    }
-                },
-                MDXLayer::WordsValue | MDXLayer::MeaningsValue => {
-                    written_size += 4 + 4 + item.1.data.len() as u64;
-                    file.write(&item.1.packtype.to_be_bytes()[..])?;
-                    file.write(&item.1.adler32.to_be_bytes()[..])?;
-                    file.write(&item.1.data[..])?;
-                }
-            }
+        if start < items.len() {
+            blocks.push(start..items.len());
         }
-        Ok((block_count, written_size))
+        blocks
     };
 
     log::info!("[+] Write word infos and values...");
-    let word_layout_offset = dstmdx.seek(SeekFrom::Current(0))?;
-    // layout + adler32 + packtype + adler32
-    let hole: Vec<u8> = vec![0; 40 + 4 + 4 + 4];
-    dstmdx.write(&hole[..])?;
-    let ret = write_mdx_layer(&mut dstmdx, &mut offtbls, MDXLayer::WordsInfo)?;
-    let (_word_block_count, word_info_size) = ret;
-    let ret = write_mdx_layer(&mut dstmdx, &mut offtbls, MDXLayer::WordsValue)?;
-    let (word_block_count, word_block_size) = ret;
-    // word infos contain additional packtype and adler32 field
-    let word_info_size = 4 + 4 + word_info_size;
-    let word_info_unpack_size = word_info_size - 8;
-
-    log::info!("[+] Write layout for words...");
-    // calculate adler32 of infos and infos layout
-    dstmdx.seek(SeekFrom::Start(word_layout_offset + 40 + 4 + 4 + 4))?;
-    let mut reader = BufReader::new(&dstmdx);
-    let mut infosbuf = vec![0u8; (word_info_size - 4 - 4) as usize];
-    reader.read_exact(&mut infosbuf).context(elog!("cannot read infosbuf"))?;
-    let mut adler = Adler32::new();
-    adler.write_slice(&infosbuf[..]);
-    let infosbuf_adler32 = adler.checksum();
-    let mut infos_layout_buf = vec![];
-    infos_layout_buf.append(&mut word_block_count.to_be_bytes().to_vec());
-    infos_layout_buf.append(&mut word_count.to_be_bytes().to_vec());
-    infos_layout_buf.append(&mut word_info_unpack_size.to_be_bytes().to_vec());
-    infos_layout_buf.append(&mut word_info_size.to_be_bytes().to_vec());
-    infos_layout_buf.append(&mut word_block_size.to_be_bytes().to_vec());
+    // word text is terminated by NUL which is two bytes for UTF-16
+    let nullchar: &[u8] = if options.encoding == MdxEncoding::Utf16 { b"\x00\x00" } else { b"\x00" };
+    // size of word is counted by characters of its encoding without NUL
+    let word_size = |word: &[u8]| (word.len() / nullchar.len()) as u16;
+    let (mut infos, mut blocks) = (vec![], vec![]);
+    let mut offset = 0u64;
+    let word_blocks = split_blocks(&mut items.iter().map(|x| 8 + x.0.len() + nullchar.len()));
+    for range in word_blocks.iter() {
+        // meaning_offset and word_text
+        let mut rawdata = vec![];
+        for (word, meaning) in items[range.clone()].iter() {
+            rawdata.extend(offset.to_be_bytes());
+            rawdata.extend(word);
+            rawdata.extend(nullchar);
+            offset += meaning.len() as u64;
+        }
+        let block = mdx_pack(&rawdata, options.compression, options.level)?;
+        let (first, last) = (&items[range.start].0, &items[range.end - 1].0);
+        infos.extend((range.len() as u64).to_be_bytes());
+        infos.extend(word_size(first).to_be_bytes());
+        infos.extend(first);
+        infos.extend(nullchar);
+        infos.extend(word_size(last).to_be_bytes());
+        infos.extend(last);
+        infos.extend(nullchar);
+        infos.extend((block.len() as u64).to_be_bytes());
+        infos.extend((rawdata.len() as u64).to_be_bytes());
+        blocks.extend(block);
+    }
+    // word infos are always compressed by zlib if compression is enabled since LZO is not
+    // supported there by most mdict readers
+    let infos_compression = match options.compression {
+        MdxCompression::None => MdxCompression::None,
+        _ => MdxCompression::Zlib,
+    };
+    let packed_infos = mdx_pack(&infos, infos_compression, options.level)?;
+    let mut layout = vec![];
+    layout.extend((word_blocks.len() as u64).to_be_bytes());
+    layout.extend((items.len() as u64).to_be_bytes());
+    layout.extend((infos.len() as u64).to_be_bytes());
+    layout.extend((packed_infos.len() as u64).to_be_bytes());
+    layout.extend((blocks.len() as u64).to_be_bytes());
     let mut adler = Adler32::new();
-    adler.write_slice(&infos_layout_buf[..]);
-    let infos_layout_adler32 = adler.checksum();
-    // write words infos layout
-    dstmdx.seek(SeekFrom::Start(word_layout_offset))?;
-    dstmdx.write(&infos_layout_buf[..])?;
-    dstmdx.write(&infos_layout_adler32.to_be_bytes()[..])?;
-    let packtype: u32 = 0x00;
-    dstmdx.write(&packtype.to_be_bytes()[..])?;
-    dstmdx.write(&infosbuf_adler32.to_be_bytes()[..])?;
+    adler.write_slice(&layout[..]);
+    dstmdx.write_all(&layout[..])?;
+    dstmdx.write_all(&adler.checksum().to_be_bytes()[..])?;
+    dstmdx.write_all(&packed_infos[..])?;
+    dstmdx.write_all(&blocks[..])?;
 
     log::info!("[+] Write meaning info and values...");
-    let meaning_layout_offset = word_layout_offset + 40 + 4 + word_info_size + word_block_size;
-    dstmdx.seek(SeekFrom::Start(meaning_layout_offset))?;
-    let hole: Vec<u8> = vec![0; 32];
-    dstmdx.write(&hole[..])?;
-    let ret = write_mdx_layer(&mut dstmdx, &mut offtbls, MDXLayer::MeaningsInfo)?;
-    let (_meaning_block_count, meaning_info_size) = ret;
-    let ret = write_mdx_layer(&mut dstmdx, &mut offtbls, MDXLayer::MeaningsValue)?;
-    let (meaning_block_count, meaning_block_size) = ret;
-
-    log::info!("[+] Write layout for meanings ...");
-    dstmdx.seek(SeekFrom::Start(meaning_layout_offset))?;
-    dstmdx.write(&meaning_block_count.to_be_bytes()[..])?;
-    dstmdx.write(&word_count.to_be_bytes()[..])?;
-    dstmdx.write(&meaning_info_size.to_be_bytes()[..])?;
-    dstmdx.write(&meaning_block_size.to_be_bytes()[..])?;
+    let (mut infos, mut blocks) = (vec![], vec![]);
+    let meaning_blocks = split_blocks(&mut items.iter().map(|x| x.1.len()));
+    for range in meaning_blocks.iter() {
+        let rawdata = items[range.clone()].iter().flat_map(|x| x.1.iter().copied()).collect::<Vec<u8>>();
+        let block = mdx_pack(&rawdata, options.compression, options.level)?;
+        infos.extend((block.len() as u64).to_be_bytes());
+        infos.extend((rawdata.len() as u64).to_be_bytes());
+        blocks.extend(block);
+    }
+    dstmdx.write_all(&(meaning_blocks.len() as u64).to_be_bytes()[..])?;
+    dstmdx.write_all(&(items.len() as u64).to_be_bytes()[..])?;
+    dstmdx.write_all(&(infos.len() as u64).to_be_bytes()[..])?;
+    dstmdx.write_all(&(blocks.len() as u64).to_be_bytes()[..])?;
+    dstmdx.write_all(&infos[..])?;
+    dstmdx.write_all(&blocks[..])?;
+    dstmdx.flush()?;
 
     Ok(())
 }
 
-pub async fn save_into_db(dict: Vec<(String, String)>, dburl: &str, table: &str) -> AnyResult<()> {
+/// Save `(word, meaning)` pairs of `entries` such as [MdxReader::entries] into postgres table
+pub async fn save_into_db<I>(entries: I, dburl: &str, table: &str) -> AnyResult<()>
+    where I: Iterator<Item = AnyResult<(String, String)>>
+{
     let pool = PgPoolOptions::new().max_connections(5).connect(dburl).await?;
     sqlx::query(format!("CREATE TABLE IF NOT EXISTS {} (word TEXT UNIQUE, meaning TEXT)", table).as_str()).execute(&pool).await?;
-    for word_meaing in dict {
-        let (word, meaning) = word_meaing;
+    for word_meaing in entries {
+        let (word, meaning) = word_meaing?;
         sqlx::query(format!("INSERT INTO {} (word, meaning) VALUES ($1, $2) ON CONFLICT (word) DO NOTHING", table).as_str())
             .bind(word)
             .bind(meaning)
@@ -846,7 +1342,9 @@ pub async fn save_into_db(dict: Vec<(String, String)>, dburl: &str, table: &str)
     Ok(())
 }
 
-pub fn write_into_text<P>(dict: &MDXDict, output: P) -> AnyResult<()>
+/// Write entries of mdx into text file `output`, and its header is written as comments of `x.toml`
+/// alongside with the output
+pub fn write_into_text<P>(reader: &MdxReader, output: P) -> AnyResult<()>
     where P: AsRef<Path>
 {
     let output = output.as_ref();
@@ -856,7 +1354,8 @@ pub fn write_into_text<P>(dict: &MDXDict, output: P) -> AnyResult<()>
         .truncate(true)
         .open(output)
         .context(elog!("Cannot open {:?}", output.display()))?;
-    for (word, meaning) in dict.entries.iter() {
+    for entry in reader.entries() {
+        let (word, meaning) = entry?;
         let word = word.trim();
         // we do not need the messy `^M` character
         let meaning = meaning.replace("\r\n", "\n");
@@ -872,10 +1371,14 @@ pub fn write_into_text<P>(dict: &MDXDict, output: P) -> AnyResult<()>
             .truncate(true)
             .open(metafile.as_path())
             .context(elog!("Cannot open {:?}", metafile.as_path().display()))?;
-        let mut kv = dict.header.iter().collect::<Vec<(&String, &String)>>();
+        let mut kv = reader.header.iter().collect::<Vec<(&String, &String)>>();
         kv.sort_by(|a, b| a.0.cmp(b.0));
+        // values such as `StyleSheet` may span multiple lines, every line is commented so that
+        // the profile is still valid
         for (k, v) in kv {
-            metafile.write(format!("# {}: {}\n", k.trim(), v.trim()).as_bytes())?;
+            for line in format!("{}: {}", k.trim(), v.trim()).lines() {
+                metafile.write(format!("# {}\n", line).as_bytes())?;
+            }
         }
     }
     Ok(())
@@ -883,7 +1386,14 @@ pub fn write_into_text<P>(dict: &MDXDict, output: P) -> AnyResult<()>
 
 #[cfg(test)]
 mod tests {
-    use crate::mdict::parse_mdx;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use std::sync::Arc;
+    use adler::Adler32;
+    use crate::mdict::{parse_mdx, parse_mdd, create_mdx, create_mdd, MDXInfo, mdx_decode};
+    use crate::mdict::{MdxDictionary, MdxWriteOptions, MdxCompression, MdxEncoding, parse_stylesheet, apply_stylesheet};
+    use crate::wikit::WikitDictionary;
+
     // this test is not a real unit-test but only for dirty and quick development
     #[test]
     fn test_parse_mdx() {
@@ -894,13 +1404,169 @@ mod tests {
         }
     }
 
-    use crate::mdict::create_mdx;
-    use std::path::Path;
+    #[test]
+    fn test_parse_mdd() {
+        let mddpath = option_env!("TEST_MDD_FILE");
+        if let Some(mddpath) = mddpath {
+            let resources = parse_mdd(mddpath);
+            assert!(resources.is_ok(), "{}:{:?}", "test mdd parsing failed", resources.map(|x| x.len()));
+        }
+    }
+
+    #[test]
+    fn test_mdx_dictionary() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut content = String::from("Apple\nfruit\n</>\napples\n@@@LINK=Apple\n</>\n中文\n汉语\n</>\n");
+        for i in 0..2000 {
+            content.push_str(&format!("word{}\nmeaning of word{}\n</>\n", i, i));
+        }
+        std::fs::write(dir.path().join("demo.txt"), content).unwrap();
+        let profile = [
+            r#"name = "demo""#,
+            r#"version = "1.0""#,
+            r#"authors = ["alice", "bob"]"#,
+            r#"distributors = []"#,
+            r#"description = "<b>demo</b> & test""#,
+            r#"homepage = """#,
+            r#"css = "@style.css""#,
+            r#"js = """#,
+            r#"stylesheet = "1\n<b>\n</b>\n2\n\n\n""#,
+        ];
+        std::fs::write(dir.path().join("demo.toml"), profile.join("\n")).unwrap();
+        std::fs::write(dir.path().join("style.css"), "b {}").unwrap();
+        let profile = crate::wikit::WikitDictProfile::load(dir.path().join("demo.txt")).unwrap();
+        let options = [
+            MdxWriteOptions::default(),
+            MdxWriteOptions { block_size: 256, compression: MdxCompression::Zlib, level: 9, encoding: MdxEncoding::Utf16 },
+            MdxWriteOptions { block_size: 1, compression: MdxCompression::Lzo, level: 0, encoding: MdxEncoding::Gb18030 },
+        ];
+        for option in options.iter() {
+            let mdxpath = dir.path().join("demo.mdx");
+            create_mdx(&profile, dir.path().join("demo.txt").as_path(), mdxpath.as_path(), option).unwrap();
+
+            let dict = MdxDictionary::load(&mdxpath).unwrap();
+            assert_eq!(dict.name(), "demo");
+            assert_eq!(dict.get_style(), "b {}");
+            let exact = crate::index::LookupOptions { max_distance: Some(0), ..Default::default() };
+            let words = dict.lookup("apple", &exact).unwrap();
+            assert_eq!(words.len(), 1);
+            assert_eq!(words[0].0, "Apple");
+            assert!(words[0].1.starts_with("fruit"));
+            assert_eq!(dict.lookup("apples", &exact).unwrap()[0].0, "Apple");
+            for i in [0, 999, 1999] {
+                let words = dict.lookup(format!("word{}", i), &exact).unwrap();
+                assert!(words[0].1.starts_with(&format!("meaning of word{}", i)), "{:?}: {:?}", option, words);
+            }
+            assert_eq!(dict.prefix("word19", 3).unwrap().len(), 3);
+            assert_eq!(dict.lookup("中文", &exact).unwrap()[0].1, "汉语");
+            let mdx = parse_mdx(mdxpath.to_str().unwrap(), None).unwrap();
+            assert_eq!(mdx.entries.len(), 2003);
+            assert_eq!(mdx.header["Description"], "<b>demo</b> & test");
+            assert_eq!(mdx.header["Author"], "alice, bob");
+            let styles = parse_stylesheet(&mdx.header["StyleSheet"]);
+            assert_eq!(apply_stylesheet("`1`bold`2`plain", &styles), "<b>bold</b>plain");
+        }
+    }
+
+    #[test]
+    fn test_mdx_dictionary_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("cached.txt"), "apple\nfruit\n</>\nbanana\nyellow fruit\n</>\n").unwrap();
+        let mdxpath = dir.path().join("cached.mdx");
+        let profile = crate::wikit::WikitDictProfile::load(dir.path().join("cached.txt")).unwrap();
+        create_mdx(&profile, dir.path().join("cached.txt").as_path(), mdxpath.as_path(), &MdxWriteOptions::default()).unwrap();
+
+        // the index is built once and both lookups go through the same loaded dictionary
+        let uri = url::Url::from_file_path(&mdxpath).unwrap().to_string();
+        let first = crate::cache::load_dictionary(&uri).unwrap();
+        let second = crate::cache::load_dictionary(&uri).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        let exact = crate::index::LookupOptions { max_distance: Some(0), ..Default::default() };
+        match (first.as_ref(), second.as_ref()) {
+            (WikitDictionary::Mdx(a), WikitDictionary::Mdx(b)) => {
+                assert_eq!(a.lookup("apple", &exact).unwrap()[0].1, "fruit");
+                assert_eq!(b.lookup("banana", &exact).unwrap()[0].1, "yellow fruit");
+            },
+            _ => panic!("{} is not loaded as mdx dictionary", uri),
+        }
+    }
+
+    #[test]
+    fn test_parse_mdx3() {
+        // only the header is read, sections following it are not decoded
+        let header = b"<Dictionary GeneratedByEngineVersion=\"3.0\" Title=\"v3\" />\r\n\x00";
+        let mut adler = Adler32::new();
+        adler.write_slice(header);
+        let mut mdx = (header.len() as u32).to_be_bytes().to_vec();
+        mdx.extend(header);
+        mdx.extend(adler.checksum().to_le_bytes());
+        let dir = tempfile::tempdir().unwrap();
+        let mdxpath = dir.path().join("v3.mdx");
+        std::fs::write(&mdxpath, mdx).unwrap();
+        let e = parse_mdx(mdxpath.to_str().unwrap(), None).unwrap_err();
+        assert!(e.to_string().contains("3.0 engine is not supported"), "{}", e);
+        let e = MdxDictionary::load(&mdxpath).unwrap_err();
+        assert!(e.to_string().contains("3.0 engine is not supported"), "{}", e);
+    }
+
+    #[test]
+    fn test_mdx_decode() {
+        let header = |encoding: &str| {
+            let mut meta = HashMap::new();
+            meta.insert("GeneratedByEngineVersion".to_string(), "2.0".to_string());
+            meta.insert("Encoding".to_string(), encoding.to_string());
+            MDXInfo::new(&meta).unwrap()
+        };
+        assert_eq!(mdx_decode(&header(""), "中文\0".as_bytes()).unwrap(), "中文");
+        assert_eq!(mdx_decode(&header("UTF-16"), &[0x2d, 0x4e, 0x87, 0x65, 0, 0]).unwrap(), "中文");
+        assert_eq!(mdx_decode(&header("GBK"), &[0xd6, 0xd0, 0xce, 0xc4]).unwrap(), "中文");
+        assert_eq!(mdx_decode(&header("Big5"), &[0xa4, 0xa4, 0xa4, 0xe5]).unwrap(), "中文");
+        assert_eq!(mdx_decode(&header("ISO-8859-1"), &[0x63, 0x61, 0x66, 0xe9]).unwrap(), "café");
+        assert!(header("UTF-16").utf16());
+    }
+
+    #[test]
+    fn test_create_mdd() {
+        let srcdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(srcdir.path().join("img")).unwrap();
+        std::fs::write(srcdir.path().join("img/cat.png"), b"cat").unwrap();
+        std::fs::write(srcdir.path().join("一.mp3"), b"one").unwrap();
+        std::fs::write(srcdir.path().join("empty.css"), b"").unwrap();
+        let dstdir = tempfile::tempdir().unwrap();
+        let dstpath = dstdir.path().join("demo.mdd");
+        create_mdd(srcdir.path(), dstpath.as_path()).unwrap();
+
+        let resources = parse_mdd(dstpath.to_str().unwrap()).unwrap();
+        assert_eq!(resources, vec![
+            ("\\empty.css".to_string(), b"".to_vec()),
+            ("\\img\\cat.png".to_string(), b"cat".to_vec()),
+            ("\\一.mp3".to_string(), b"one".to_vec()),
+        ]);
+    }
+
     #[test]
     fn test_create_mdx() {
         let srcpath = Path::new("test/demo.txt");
         let dstpath = Path::new("test/demo.mdx");
-        let r = create_mdx("title", "author", "description", srcpath, dstpath);
+        let profile = crate::wikit::WikitDictProfile::load(srcpath).unwrap();
+        let r = create_mdx(&profile, srcpath, dstpath, &MdxWriteOptions::default());
         assert!(r.is_ok(), "{}:{:?}", "create mdx failed", r);
     }
+
+    #[test]
+    fn test_create_mdx_key_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let srcpath = dir.path().join("case.txt");
+        let content = ["USA", "apple", "Paris", "banana", "paris"].iter().map(|w| format!("{}\n{}\n</>\n", w, w)).collect::<String>();
+        std::fs::write(&srcpath, content).unwrap();
+        let dstpath = dir.path().join("case.mdx");
+        let words = |profile: &str| {
+            std::fs::write(dir.path().join("case.toml"), profile).unwrap();
+            let profile = crate::wikit::WikitDictProfile::load(&srcpath).unwrap();
+            create_mdx(&profile, srcpath.as_path(), dstpath.as_path(), &MdxWriteOptions::default()).unwrap();
+            crate::mdict::MdxReader::open(&dstpath).unwrap().entries().map(|x| x.unwrap().0).collect::<Vec<_>>()
+        };
+        assert_eq!(words(""), vec!["apple", "banana", "Paris", "paris", "USA"]);
+        assert_eq!(words("key_case_sensitive = true"), vec!["Paris", "USA", "apple", "banana", "paris"]);
+    }
 }
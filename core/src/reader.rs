@@ -54,7 +54,7 @@ impl Iterator for MDXSource {
             meaning
         };
         meaning.push(0 as char);
-        return Some((util::normalize_word(word), meaning));
+        return Some((util::trim_word(word), meaning));
     }
 }
 
@@ -1,6 +1,9 @@
+use crate::cache;
 use crate::config;
 use crate::wikit;
 use crate::crypto;
+use crate::index::{LookupOptions, PatternSyntax, MAX_LOOKUP_DISTANCE};
+use crate::fulltext::FulltextHit;
 
 use std::net::{IpAddr, Ipv4Addr};
 use std::{sync::Mutex, collections::HashMap};
@@ -11,10 +14,21 @@ use rocket::serde::json::Json;
 use once_cell::sync::Lazy;
 use wikit_proto::DictMeta;
 
+// default number of words returned by prefix query
+const PREFIX_LIMIT: usize = 20;
+// default number of words returned by full-text search and pattern search
+const SEARCH_LIMIT: usize = 20;
+
 pub static DICTMP: Lazy<Arc<Mutex<HashMap<String, String>>>> = Lazy::new(|| {
     Arc::new(Mutex::new(HashMap::new()))
 });
 
+// Get loaded dictionary of `dictname` which is registered by `/list`, the lock of DICTMP is
+// released before the dictionary is used
+fn get_dictionary(dictname: &str) -> Option<Arc<wikit::WikitDictionary>> {
+    let uri = DICTMP.lock().ok()?.get(dictname)?.clone();
+    cache::load_dictionary(uri)
+}
 
 #[catch(500)]
 fn internal_error() -> &'static str {
@@ -32,27 +46,30 @@ async fn list() -> Json<Vec<DictMeta>> {
     if let Ok(config) = config::load_config() {
         for uri in config.srvcfg.uris.iter() {
             let dictid = crypto::md5(uri.as_bytes());
-            if let Some(dict) = wikit::load_dictionary_from_uri(uri) {
+            if let Some(dict) = cache::load_dictionary(uri) {
                 let style_key = format!("style[{}]", dictid);
                 let script_key = format!("script[{}]", dictid);
-                if let wikit::WikitDictionary::Local(d) = dict {
-                    if let Ok(mut dictmp) = DICTMP.lock() {
-                        if dictmp.get(&style_key).is_none() {
-                            dictmp.insert(style_key, d.head.style.clone());
-                        }
-                        if dictmp.get(&script_key).is_none() {
-                            dictmp.insert(script_key, d.head.script.clone());
-                        }
+                let (name, style, script) = match dict.as_ref() {
+                    wikit::WikitDictionary::Local(d) => (d.head.name.clone(), d.head.style.clone(), d.head.script.clone()),
+                    wikit::WikitDictionary::Mdx(d) => (d.name(), d.style.clone(), d.script.clone()),
+                    wikit::WikitDictionary::Remote(_) => continue,
+                };
+                if let Ok(mut dictmp) = DICTMP.lock() {
+                    if dictmp.get(&style_key).is_none() {
+                        dictmp.insert(style_key, style);
                     }
-                    if let Ok(mut dictmp) = DICTMP.lock() {
-                        dictmp.insert(dictid.clone(), uri.to_string());
+                    if dictmp.get(&script_key).is_none() {
+                        dictmp.insert(script_key, script);
                     }
-
-                    dictlist.push(DictMeta {
-                        name: d.head.name,
-                        id: dictid.clone(),
-                    });
                 }
+                if let Ok(mut dictmp) = DICTMP.lock() {
+                    dictmp.insert(dictid.clone(), uri.to_string());
+                }
+
+                dictlist.push(DictMeta {
+                    name,
+                    id: dictid.clone(),
+                });
             }
         }
     }
@@ -81,30 +98,94 @@ async fn script(dictname: String) -> String {
     "".to_string()
 }
 
-#[get("/query?<word>&<dictname>")]
-async fn query(word: String, dictname: String) -> Json<Vec<(String, String)>> {
+#[get("/query?<word>&<dictname>&<distance>&<limit>&<exact>&<transpose>")]
+async fn query(
+    word: String,
+    dictname: String,
+    distance: Option<u32>,
+    limit: Option<usize>,
+    exact: Option<bool>,
+    transpose: Option<bool>,
+) -> Json<Vec<(String, String)>> {
+    let default = LookupOptions::default();
+    let options = LookupOptions {
+        max_distance: distance.map(|x| x.min(MAX_LOOKUP_DISTANCE)),
+        limit: limit.unwrap_or(default.limit),
+        exact: exact.unwrap_or(default.exact),
+        transpose: transpose.unwrap_or(default.transpose),
+    };
     let r = vec![];
-    if let Ok(dictmp) = DICTMP.lock() {
-        if let Some(uri) = dictmp.get(dictname.as_str()) {
-            if let Some(dict) = wikit::load_dictionary_from_uri(uri) {
-                match dict {
-                    wikit::WikitDictionary::Local(d) => {
-                        if let Ok(r) = d.lookup(word) {
-                            return Json(r);
-                        }
-                    },
-                    wikit::WikitDictionary::Remote(d) => {
-                        if let Ok(r) = d.lookup(&word, &dictname) {
-                            return Json(r);
-                        }
-                    },
+    if let Some(dict) = get_dictionary(&dictname) {
+        match dict.as_ref() {
+            wikit::WikitDictionary::Local(d) => {
+                if let Ok(r) = d.lookup(word, &options) {
+                    return Json(r);
                 }
-            }
+            },
+            wikit::WikitDictionary::Remote(d) => {
+                if let Ok(r) = d.lookup(&word, &dictname, &options) {
+                    return Json(r);
+                }
+            },
+            wikit::WikitDictionary::Mdx(d) => {
+                if let Ok(r) = d.lookup(word, &options) {
+                    return Json(r);
+                }
+            },
         }
     }
     return Json(r);
 }
 
+#[get("/prefix?<word>&<dictname>&<limit>")]
+async fn prefix(word: String, dictname: String, limit: Option<usize>) -> Json<Vec<(String, String)>> {
+    let limit = limit.unwrap_or(PREFIX_LIMIT);
+    if let Some(dict) = get_dictionary(&dictname) {
+        let r = match dict.as_ref() {
+            wikit::WikitDictionary::Local(d) => d.prefix(word, limit),
+            wikit::WikitDictionary::Remote(d) => d.prefix(&word, &dictname, limit),
+            wikit::WikitDictionary::Mdx(d) => d.prefix(word, limit).map_err(|e| e.into()),
+        };
+        if let Ok(r) = r {
+            return Json(r);
+        }
+    }
+    Json(vec![])
+}
+
+#[get("/search?<query>&<dictname>&<limit>")]
+async fn search(query: String, dictname: String, limit: Option<usize>) -> Json<Vec<FulltextHit>> {
+    let limit = limit.unwrap_or(SEARCH_LIMIT);
+    if let Some(dict) = get_dictionary(&dictname) {
+        if let wikit::WikitDictionary::Local(d) = dict.as_ref() {
+            if let Ok(r) = d.search_fulltext(query, limit) {
+                return Json(r);
+            }
+        }
+    }
+    Json(vec![])
+}
+
+#[get("/pattern?<pattern>&<dictname>&<syntax>&<limit>")]
+async fn pattern(pattern: String, dictname: String, syntax: Option<String>, limit: Option<usize>) -> Json<Vec<(String, String)>> {
+    let limit = limit.unwrap_or(SEARCH_LIMIT);
+    let syntax = match syntax {
+        Some(syntax) => PatternSyntax::from_name(syntax),
+        None => Some(PatternSyntax::Glob),
+    };
+    if let (Some(syntax), Some(dict)) = (syntax, get_dictionary(&dictname)) {
+        let r = match dict.as_ref() {
+            wikit::WikitDictionary::Local(d) => d.pattern(pattern, syntax, limit).ok(),
+            wikit::WikitDictionary::Mdx(d) => d.pattern(pattern, syntax, limit).ok(),
+            _ => None,
+        };
+        if let Some(r) = r {
+            return Json(r);
+        }
+    }
+    Json(vec![])
+}
+
 pub fn rocket() -> rocket::Rocket<Build> {
     let cfg = match config::load_config() {
         Ok(cfg) => {
@@ -120,6 +201,6 @@ pub fn rocket() -> rocket::Rocket<Build> {
         }
     };
     rocket::custom(&cfg)
-        .mount("/wikit/", routes![query, list, style, script])
+        .mount("/wikit/", routes![query, prefix, search, pattern, list, style, script])
         .register("/", catchers![internal_error, not_found])
 }
@@ -1,12 +1,15 @@
 use crate::elog;
 use crate::error::{AnyResult, Context};
+use crate::index;
 
 use std::process::Command;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::io::{Error, ErrorKind};
 use std::net::TcpListener;
 
+use unicode_normalization::UnicodeNormalization;
+
 struct ArgParser<'a> {
     buf: &'a str,
     consumed: usize,
@@ -77,6 +80,11 @@ impl<'a> Iterator for ArgParser<'a> {
 }
 
 pub fn runcmd(cmd: &str, envs: Option<Vec<(String, String)>>) -> AnyResult<String> {
+    runcmd_in(cmd, envs, None)
+}
+
+/// Run `cmd` like [runcmd] in working directory `dir`, the current directory is used if it is none
+pub fn runcmd_in(cmd: &str, envs: Option<Vec<(String, String)>>, dir: Option<&Path>) -> AnyResult<String> {
     let argparser = ArgParser::new(cmd);
     let cmd: Vec<String> = argparser.into_iter().collect();
     let envs: HashMap<String, String> = if let Some(envs) = envs {
@@ -84,11 +92,15 @@ pub fn runcmd(cmd: &str, envs: Option<Vec<(String, String)>>) -> AnyResult<Strin
     } else {
         HashMap::new()
     };
-    let outbuf = match cmd.len() {
+    let mut command = match cmd.len() {
         0 => return Err(elog!("Empty command")),
-        1 => Command::new(&cmd[0]).envs(&envs).output(),
-        _ => Command::new(&cmd[0]).envs(&envs).args(&cmd[1..]).output(),
+        _ => Command::new(&cmd[0]),
     };
+    command.envs(&envs).args(&cmd[1..]);
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    let outbuf = command.output();
     let outbuf = outbuf.context(elog!("failed to run command {:?}", cmd))?;
     if !outbuf.status.success() {
         let err = match std::str::from_utf8(&outbuf.stderr[..]) {
@@ -144,9 +156,240 @@ pub fn filter_file_by_suffix<P>(path: P, suffix: &str) -> Option<Vec<PathBuf>> w
     list.ok()
 }
 
+/// Remove leading and trailing control characters and whitespaces of headword, the case of
+/// headword is kept for display
+pub fn trim_word<S>(word: S) -> String where S: AsRef<str> {
+    word.as_ref().trim_matches(|c: char| c.is_control() || c.is_whitespace()).to_string()
+}
+
 pub fn normalize_word<S>(word: S) -> String where S: AsRef<str> {
-    let word = word.as_ref().trim_matches(|c: char| c.is_control() || c.is_whitespace());
-    word.to_lowercase()
+    trim_word(word).to_lowercase()
+}
+
+/// Normalize word into index key by NFKC and [normalize_word], accents such as `é` are folded
+/// into `e` if `fold_accents` is true
+pub fn normalize_key<S>(word: S, fold_accents: bool) -> String where S: AsRef<str> {
+    let word = if fold_accents {
+        // only marks of combining diacritical blocks are removed, since marks of some scripts
+        // (such as devanagari vowel signs) are essential parts of words
+        let is_diacritic = |c: char| matches!(c,
+            '\u{0300}'..='\u{036f}' | '\u{1ab0}'..='\u{1aff}' | '\u{1dc0}'..='\u{1dff}' |
+            '\u{20d0}'..='\u{20ff}' | '\u{fe20}'..='\u{fe2f}'
+        );
+        word.as_ref().nfkd().filter(|c| !is_diacritic(*c)).nfkc().collect::<String>()
+    } else {
+        word.as_ref().nfkc().collect::<String>()
+    };
+    normalize_word(word)
+}
+
+/// Policy to handle multiple definitions of the same headword
+#[derive(Debug, Clone, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Keep the first definition
+    First,
+    /// Keep the last definition
+    Last,
+    /// Concatenate all definitions with the separator
+    Concat(String),
+    /// Keep all definitions as multiple entries
+    All,
+}
+
+impl Default for DuplicatePolicy {
+    fn default() -> Self {
+        DuplicatePolicy::First
+    }
+}
+
+impl DuplicatePolicy {
+    /// Get policy from name which is one of `first` (default for empty name), `last`, `concat`
+    /// and `all`, the `separator` is used by `concat` which is `<hr>` if it is none
+    pub fn from_name<S: AsRef<str>>(name: S, separator: Option<S>) -> Option<Self> {
+        match name.as_ref().trim().to_lowercase().as_str() {
+            "" | "first" => Some(DuplicatePolicy::First),
+            "last" => Some(DuplicatePolicy::Last),
+            "concat" => {
+                let separator = separator.map(|x| x.as_ref().to_string()).unwrap_or("<hr>".to_string());
+                Some(DuplicatePolicy::Concat(separator))
+            },
+            "all" => Some(DuplicatePolicy::All),
+            _ => None,
+        }
+    }
+
+    /// Join definitions of a word grouped by [group_duplicates], only `concat` groups more than
+    /// one definition
+    pub fn join(&self, mut meanings: Vec<String>) -> String {
+        if meanings.len() == 1 {
+            return meanings.pop().unwrap_or_default();
+        }
+        let separator = match self {
+            DuplicatePolicy::Concat(separator) => separator.as_str(),
+            _ => "",
+        };
+        // meanings may be terminated by NUL
+        meanings.iter().map(|x| x.trim_end_matches(char::from(0))).collect::<Vec<&str>>().join(separator)
+    }
+}
+
+/// Merge `(word, meaning)` list by `policy` for words with multiple definitions
+///
+/// The order of words is kept by their first occurrences, and the number of words with multiple
+/// definitions is returned too.
+pub fn merge_duplicates(words: Vec<(String, String)>, policy: &DuplicatePolicy) -> (Vec<(String, String)>, usize) {
+    let (groups, dupcnt) = group_duplicates(words, policy);
+    let words = groups.into_iter().map(|(word, meanings)| (word, policy.join(meanings))).collect();
+    (words, dupcnt)
+}
+
+/// Group `(word, meaning)` list by `policy` into `(word, meanings)` list, where `meanings` are the
+/// definitions to be joined by [DuplicatePolicy::join]
+///
+/// Unlike [merge_duplicates], meaning can be a reference to the definition such as its position
+/// in source, so definitions need not be kept in memory. The order of words is kept by their
+/// first occurrences, and the number of words with multiple definitions is returned too.
+pub fn group_duplicates<M>(words: Vec<(String, M)>, policy: &DuplicatePolicy) -> (Vec<(String, Vec<M>)>, usize) {
+    let mut merged: Vec<(String, Vec<M>)> = vec![];
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for (word, meaning) in words {
+        match positions.get(&word) {
+            Some(pos) => merged[*pos].1.push(meaning),
+            None => {
+                positions.insert(word.clone(), merged.len());
+                merged.push((word, vec![meaning]));
+            }
+        }
+    }
+
+    let dupcnt = merged.iter().filter(|x| x.1.len() > 1).count();
+    let mut words = vec![];
+    for (word, mut meanings) in merged {
+        match policy {
+            DuplicatePolicy::First => {
+                meanings.truncate(1);
+                words.push((word, meanings));
+            },
+            DuplicatePolicy::Last => {
+                let last = meanings.pop().into_iter().collect();
+                words.push((word, last));
+            },
+            DuplicatePolicy::Concat(_) => words.push((word, meanings)),
+            DuplicatePolicy::All => words.extend(meanings.into_iter().map(|meaning| (word.clone(), vec![meaning]))),
+        }
+    }
+    (words, dupcnt)
+}
+
+/// Marker of MDX redirect entry whose meaning is `@@@LINK=otherword`
+pub const LINK_MARKER: &str = "@@@LINK=";
+
+/// Get target word of redirect entry, none is returned if `meaning` is not a redirect
+pub fn link_target(meaning: &str) -> Option<&str> {
+    let meaning = meaning.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    let target = meaning.strip_prefix(LINK_MARKER)?.trim();
+    // multiple links may be separated by newline, the first one is used
+    target.lines().next().map(|x| x.trim())
+}
+
+/// Error of following redirect entries
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    /// Target word of the link does not exist
+    Missing(String),
+    /// The links refer to each other
+    Cycle(String),
+}
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LinkError::Missing(target) => write!(f, "target {} is missing", target),
+            LinkError::Cycle(target) => write!(f, "cycle is found at {}", target),
+        }
+    }
+}
+
+/// Follow redirect entries starting from `(word, meaning)` until a real entry is reached
+///
+/// `lookup` returns `(word, meaning)` of the given target word. The `(word, meaning)` itself is
+/// returned if it is not a redirect.
+pub fn follow_links<F>(word: String, meaning: String, mut lookup: F) -> Result<(String, String), LinkError>
+where
+    F: FnMut(&str) -> Option<(String, String)>,
+{
+    let mut visited = HashSet::new();
+    visited.insert(word.clone());
+    let (mut word, mut meaning) = (word, meaning);
+    while let Some(target) = link_target(&meaning).map(|x| x.to_string()) {
+        if !visited.insert(target.clone()) {
+            return Err(LinkError::Cycle(target));
+        }
+        (word, meaning) = lookup(&target).ok_or(LinkError::Missing(target))?;
+    }
+    Ok((word, meaning))
+}
+
+/// Split `(word, meaning)` list into real entries and aliases
+///
+/// The returned aliases are `(alias, target)` list where `target` is a word of real entries, a
+/// redirect entry is dropped if its word has a real entry already. Broken links are returned as
+/// `(alias, error)` list. Meaning of real entry is not read, so it can be a placeholder whose
+/// text is empty.
+///
+/// A target is matched by word exactly first, and then by its index key normalized by `norm`, so
+/// that `@@@LINK=Cafe` refers to `café` if accents are folded.
+pub fn resolve_links<M>(words: Vec<(String, M)>, norm: index::KeyNormalization) -> (Vec<(String, M)>, Vec<(String, String)>, Vec<(String, LinkError)>)
+    where M: AsRef<str>
+{
+    let (links, entries): (Vec<_>, Vec<_>) = words.into_iter().partition(|(_, meaning)| link_target(meaning.as_ref()).is_some());
+    let (mut links_map, mut links_keys): (HashMap<&str, &str>, HashMap<String, (&str, &str)>) = (HashMap::new(), HashMap::new());
+    for (word, meaning) in links.iter() {
+        links_map.entry(word).or_insert(meaning.as_ref());
+        links_keys.entry(norm.normalize(word)).or_insert((word, meaning.as_ref()));
+    }
+    let real: HashSet<&str> = entries.iter().map(|(word, _)| word.as_str()).collect();
+    let mut real_keys: HashMap<String, &str> = HashMap::new();
+    for (word, _) in entries.iter() {
+        real_keys.entry(norm.normalize(word)).or_insert(word);
+    }
+
+    let (mut aliases, mut broken) = (vec![], vec![]);
+    let mut seen = HashSet::new();
+    for (word, meaning) in links.iter() {
+        if real.contains(word.as_str()) || !seen.insert(word.as_str()) {
+            continue;
+        }
+        // only words are required to follow links, so meanings of real entries are left empty
+        let lookup = |target: &str| {
+            if real.contains(target) {
+                return Some((target.to_string(), String::new()));
+            }
+            if let Some(meaning) = links_map.get(target) {
+                return Some((target.to_string(), meaning.to_string()));
+            }
+            let key = norm.normalize(target);
+            if let Some(word) = real_keys.get(&key) {
+                return Some((word.to_string(), String::new()));
+            }
+            links_keys.get(&key).map(|(word, meaning)| (word.to_string(), meaning.to_string()))
+        };
+        match follow_links(word.clone(), meaning.as_ref().to_string(), lookup) {
+            Ok((target, _)) => aliases.push((word.clone(), target)),
+            Err(e) => broken.push((word.clone(), e)),
+        }
+    }
+    (entries, aliases, broken)
+}
+
+/// Normalize resource path such as `\\img\\cat.png` or `./img/cat.png` into `img/cat.png`
+pub fn normalize_resource_path<S>(path: S) -> String where S: AsRef<str> {
+    let path = path.as_ref().trim().replace('\\', "/");
+    let mut path = path.as_str();
+    while path.starts_with("./") || path.starts_with('/') {
+        path = path.trim_start_matches("./").trim_start_matches('/');
+    }
+    path.to_string()
 }
 
 // Get available TCP port
@@ -187,6 +430,63 @@ fn test_argparser() {
     assert_eq!(Some(" x y z  ".into()), argparser.next());
 }
 
+#[test]
+fn test_normalize_key() {
+    assert_eq!(normalize_key("Café", true), "cafe");
+    assert_eq!(normalize_key("Café", false), "café");
+    assert_eq!(normalize_key("cafe\u{301}", false), "café");
+    assert_eq!(normalize_key("ﬁnance", false), "finance");
+    assert_eq!(normalize_key("नमस्ते", true), "नमस्ते");
+    assert_eq!(normalize_key("が", true), "が");
+}
+
+#[test]
+fn test_resolve_links() {
+    let words = [("a", "@@@LINK=b"), ("b", "@@@LINK=c\r\n"), ("c", "see"), ("x", "@@@LINK=y"), ("y", "@@@LINK=x"), ("m", "@@@LINK=n")];
+    let words = words.iter().map(|(w, m)| (w.to_string(), m.to_string())).collect();
+    let (entries, aliases, broken) = resolve_links(words, index::KeyNormalization::NfkcFold);
+    assert_eq!(entries, vec![("c".to_string(), "see".to_string())]);
+    assert_eq!(aliases, vec![("a".to_string(), "c".to_string()), ("b".to_string(), "c".to_string())]);
+    assert_eq!(broken, vec![
+        ("x".to_string(), LinkError::Cycle("x".to_string())),
+        ("y".to_string(), LinkError::Cycle("y".to_string())),
+        ("m".to_string(), LinkError::Missing("n".to_string())),
+    ]);
+}
+
+#[test]
+fn test_resolve_links_normalized() {
+    let words = [("Coffee", "@@@LINK=CAFÉ"), ("drink", "@@@LINK=cafe"), ("café", "coffee")];
+    let words = words.iter().map(|(w, m)| (w.to_string(), m.to_string())).collect::<Vec<_>>();
+    let (_, aliases, broken) = resolve_links(words.clone(), index::KeyNormalization::NfkcFold);
+    assert_eq!(aliases, vec![
+        ("Coffee".to_string(), "café".to_string()),
+        ("drink".to_string(), "café".to_string()),
+    ]);
+    assert!(broken.is_empty());
+
+    // accents are kept, so `cafe` is not `café`
+    let (_, aliases, broken) = resolve_links(words, index::KeyNormalization::Nfkc);
+    assert_eq!(aliases, vec![("Coffee".to_string(), "café".to_string())]);
+    assert_eq!(broken, vec![("drink".to_string(), LinkError::Missing("cafe".to_string()))]);
+}
+
+#[test]
+fn test_merge_duplicates() {
+    let words = [("b", "1"), ("a", "2"), ("b", "3"), ("b", "4"), ("c", "5"), ("c", "6")]
+        .iter().map(|(w, m)| (w.to_string(), m.to_string())).collect::<Vec<_>>();
+    let merge = |policy| {
+        let (words, dupcnt) = merge_duplicates(words.clone(), &policy);
+        (words.iter().map(|(w, m)| format!("{}:{}", w, m)).collect::<Vec<_>>().join(" "), dupcnt)
+    };
+    assert_eq!(merge(DuplicatePolicy::First), ("b:1 a:2 c:5".to_string(), 2));
+    assert_eq!(merge(DuplicatePolicy::Last), ("b:4 a:2 c:6".to_string(), 2));
+    assert_eq!(merge(DuplicatePolicy::Concat("|".to_string())), ("b:1|3|4 a:2 c:5|6".to_string(), 2));
+    assert_eq!(merge(DuplicatePolicy::All), ("b:1 b:3 b:4 a:2 c:5 c:6".to_string(), 2));
+    assert_eq!(DuplicatePolicy::from_name("", None), Some(DuplicatePolicy::First));
+    assert_eq!(DuplicatePolicy::from_name("oops", None), None);
+}
+
 #[test]
 fn parse_path_test() {
     let p = parse_path("test/demo.txt");
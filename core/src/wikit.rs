@@ -3,26 +3,68 @@
 use crate::error::{WikitError, Context, WikitResult, AnyResult, NomResult};
 use crate::elog;
 use crate::index;
+use crate::fulltext;
 use crate::mdict;
 use crate::util;
 use crate::reader;
 use crate::config;
 
 use std::path::{Path, PathBuf};
-use std::fs::File;
-use std::io::{BufWriter, Write, Seek, SeekFrom, Read};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write, Seek, SeekFrom, Read, Cursor};
+use std::sync::Arc;
+use std::collections::{BTreeMap, HashSet};
 
 use serde::{Deserialize, Serialize};
-use nom::{do_parse, map_res, take};
+use nom::{do_parse, map_res, take, opt, complete, tuple, call};
+use compress::zlib;
+use adler::Adler32;
+use memmap::Mmap;
 use nom::number::streaming::{be_u16, be_u32, be_u64};
 use wikit_proto::DictMeta;
 
 // `516` is the birthday of wikit project (the first commit date 2021-05-16)
 const WIKIT_MAGIC: &'static str = "WIKIT516";
 // the latest wikit dictionary format version
-const LATEST_WIKIT_FMT_VERSION: u32 = 0x00_00_00_01;
+const LATEST_WIKIT_FMT_VERSION: u32 = 0x00_00_00_05;
+// the max uncompressed size of a data block, entries larger than it are put into its own block
+const MAX_DATA_BLOCK_SIZE: usize = 64 * 1024;
+// the data block offset is saved in the high bits of index value and the offset of entry in the
+// uncompressed block is saved in the low `DATA_BLOCK_OFFSET_SHIFT` bits
+const DATA_BLOCK_OFFSET_SHIFT: u64 = 24;
+// the size of section chunk covered by one checksum
+const CHECKSUM_CHUNK_SIZE: u32 = 64 * 1024;
+// the number of uncompressed mdx or mdd record blocks kept when they are read in sorted order
+const MDX_BLOCK_CACHE_SIZE: usize = 64;
 
-#[derive(Debug, Copy, Clone)]
+/// Sections of wikit dictionary file, it is used to report which part of dictionary is broken
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WikitSection {
+    Header,
+    Data,
+    Index,
+    ResourceIndex,
+    Fulltext,
+    FulltextIndex,
+    Checksum,
+}
+
+impl std::fmt::Display for WikitSection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            WikitSection::Header => "header",
+            WikitSection::Data => "data",
+            WikitSection::Index => "index",
+            WikitSection::ResourceIndex => "resource index",
+            WikitSection::Fulltext => "full-text",
+            WikitSection::FulltextIndex => "full-text index",
+            WikitSection::Checksum => "checksum",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[repr(u8)]
 pub enum DataEntryType {
     TXT = 0x1,
@@ -33,6 +75,102 @@ pub enum DataEntryType {
     WAV = 0x6,
     // Do we really need this?
     MP4 = 0x7,
+    OGG = 0x8,
+    GIF = 0x9,
+    CSS = 0xa,
+    JS = 0xb,
+    // headwords sharing the same index key and their meanings
+    WORDS = 0xc,
+    // any other resource whose type cannot be told from its file extension
+    BIN = 0xff,
+}
+
+impl DataEntryType {
+    pub fn new(v: u8) -> Option<Self> {
+        match v {
+            0x1 => Some(DataEntryType::TXT),
+            0x2 => Some(DataEntryType::SVG),
+            0x3 => Some(DataEntryType::PNG),
+            0x4 => Some(DataEntryType::JPG),
+            0x5 => Some(DataEntryType::MP3),
+            0x6 => Some(DataEntryType::WAV),
+            0x7 => Some(DataEntryType::MP4),
+            0x8 => Some(DataEntryType::OGG),
+            0x9 => Some(DataEntryType::GIF),
+            0xa => Some(DataEntryType::CSS),
+            0xb => Some(DataEntryType::JS),
+            0xc => Some(DataEntryType::WORDS),
+            0xff => Some(DataEntryType::BIN),
+            _ => None,
+        }
+    }
+
+    /// Guess entry type from the extension of a resource path
+    pub fn from_path<P>(path: P) -> Self where P: AsRef<Path> {
+        let ext = path.as_ref().extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "txt" | "htm" | "html" => DataEntryType::TXT,
+            "svg" => DataEntryType::SVG,
+            "png" => DataEntryType::PNG,
+            "jpg" | "jpeg" => DataEntryType::JPG,
+            "mp3" => DataEntryType::MP3,
+            "wav" => DataEntryType::WAV,
+            "mp4" => DataEntryType::MP4,
+            "ogg" | "oga" => DataEntryType::OGG,
+            "gif" => DataEntryType::GIF,
+            "css" => DataEntryType::CSS,
+            "js" => DataEntryType::JS,
+            _ => DataEntryType::BIN,
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            DataEntryType::TXT | DataEntryType::WORDS => "text/html",
+            DataEntryType::SVG => "image/svg+xml",
+            DataEntryType::PNG => "image/png",
+            DataEntryType::JPG => "image/jpeg",
+            DataEntryType::MP3 => "audio/mpeg",
+            DataEntryType::WAV => "audio/wav",
+            DataEntryType::MP4 => "video/mp4",
+            DataEntryType::OGG => "audio/ogg",
+            DataEntryType::GIF => "image/gif",
+            DataEntryType::CSS => "text/css",
+            DataEntryType::JS => "text/javascript",
+            DataEntryType::BIN => "application/octet-stream",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum DataBlockCompression {
+    None = 0x0,
+    Zlib = 0x1,
+    Zstd = 0x2,
+}
+
+impl DataBlockCompression {
+    pub fn new(v: u8) -> Option<Self> {
+        match v {
+            0x0 => Some(DataBlockCompression::None),
+            0x1 => Some(DataBlockCompression::Zlib),
+            0x2 => Some(DataBlockCompression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn from_name<S>(name: S) -> Option<Self> where S: AsRef<str> {
+        match name.as_ref().trim().to_lowercase().as_str() {
+            "none" => Some(DataBlockCompression::None),
+            "zlib" => Some(DataBlockCompression::Zlib),
+            "" | "zstd" => Some(DataBlockCompression::Zstd),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -47,6 +185,7 @@ pub enum WikitSourceType {
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct WikitDictProfile {
     name: String,
     version: String,
@@ -56,6 +195,125 @@ pub struct WikitDictProfile {
     homepage: String,
     css: String,
     js: String,
+    /// Directory of media resources relative to the profile, `x.media` is used if it is empty
+    media: String,
+    /// Data block compression which is one of `zstd` (default), `zlib` and `none`
+    compression: String,
+    /// Whether to build full-text index of meanings for [LocalDictionary::search_fulltext]
+    fulltext: bool,
+    /// Policy for words with multiple definitions which is one of `first` (default), `last`,
+    /// `concat` and `all`, see [util::DuplicatePolicy]
+    duplicate: String,
+    /// Separator of definitions when `duplicate` is `concat`, `<hr>` is used by default
+    separator: Option<String>,
+    /// Whether to keep accents in index keys, by default `café` is indexed as `cafe`
+    keep_accents: bool,
+    /// Style sheet of mdx output which maps style number `n` to the begin and end HTML tags of
+    /// text marked by `` `n` `` in meanings, each style takes three lines: number, begin tag and
+    /// end tag. It is expanded into meanings for macos dictionary.
+    stylesheet: String,
+    /// Whether keys of mdx output are case sensitive
+    key_case_sensitive: bool,
+    /// Directory of the profile which `@path` of `css`, `js` and `stylesheet` is relative to
+    #[serde(skip)]
+    basedir: PathBuf,
+}
+
+impl WikitDictProfile {
+    /// Load profile `x.toml` alongside with source file `x.txt` or `x.mdx`, a default profile is
+    /// returned if it does not exist, and an error is returned if it is invalid
+    ///
+    /// All fields are optional, the name of dictionary is `x` if it is not given.
+    pub fn load<P>(srcfile: P) -> WikitResult<Self> where P: AsRef<Path> {
+        let srcfile = srcfile.as_ref();
+        let (pdir, stem, _) = util::parse_path(srcfile)
+            .context(elog!("failed to get parent directory of {}", srcfile.display()))?;
+
+        let mut conf = String::new();
+        let confpath = pdir.join(stem.clone() + ".toml");
+        if let Ok(mut f) = File::open(&confpath) {
+            f.read_to_string(&mut conf)?;
+        };
+        let mut profile = toml::from_str::<WikitDictProfile>(&conf)
+            .map_err(|e| WikitError::new(format!("failed to parse {}: {}", confpath.display(), e)))?;
+        if profile.name.is_empty() {
+            profile.name = stem;
+        }
+        if profile.description.is_empty() {
+            profile.description = "this dictionary has no description".to_owned();
+        }
+        if profile.authors.is_empty() {
+            profile.authors = vec!["anonymous".to_owned()];
+        }
+        profile.basedir = pdir;
+        Ok(profile)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Authors separated by comma
+    pub fn author(&self) -> String {
+        self.authors.join(", ")
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn key_case_sensitive(&self) -> bool {
+        self.key_case_sensitive
+    }
+
+    /// Policy for words with multiple definitions given by `duplicate` and `separator`
+    pub fn duplicate_policy(&self) -> WikitResult<util::DuplicatePolicy> {
+        util::DuplicatePolicy::from_name(self.duplicate.as_str(), self.separator.as_deref())
+            .ok_or(WikitError::new(format!("unknown duplicate policy: {}", self.duplicate)))
+    }
+
+    pub fn style(&self) -> String {
+        self.read_include_file(&self.css)
+    }
+
+    pub fn script(&self) -> String {
+        self.read_include_file(&self.js)
+    }
+
+    pub fn stylesheet(&self) -> String {
+        self.read_include_file(&self.stylesheet)
+    }
+
+    /// Directory of media resources for source file `x.txt`, which is `x.media` alongside with the
+    /// source file unless `media` is given
+    pub fn media_dir<P>(&self, srcfile: P) -> PathBuf where P: AsRef<Path> {
+        if self.media.trim().len() > 0 {
+            self.basedir.join(self.media.trim())
+        } else {
+            let stem = srcfile.as_ref().file_stem().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+            self.basedir.join(stem + ".media")
+        }
+    }
+
+    // Value such as `@style.css` is the content of file relative to the profile, while inline CSS
+    // such as `@import url(x.css);` is taken as it is
+    fn read_include_file(&self, maybe_path: &str) -> String {
+        let maybe_path = maybe_path.trim();
+        let mut content = String::new();
+        if maybe_path.starts_with('@') && !maybe_path.contains(|c| matches!(c, '\n' | '{' | ';')) {
+            // TODO: avoid path traversal?
+            if let Ok(mut f) = File::open(self.basedir.join(&maybe_path[1..])) {
+                _ = f.read_to_string(&mut content);
+            }
+        } else {
+            content = maybe_path.to_owned();
+        }
+        content
+    }
 }
 
 #[derive(Debug)]
@@ -78,10 +336,112 @@ impl<'a> DataEntry<'a> {
         let end = writer.seek(SeekFrom::Current(0))?;
         Ok((start, end - start))
     }
+
+    pub fn size(&self) -> usize {
+        1 + 4 + self.buf.len()
+    }
+
+    /// Read the entry at `offset` and return its type and content
+    pub fn read<R>(reader: &mut R, offset: u64) -> WikitResult<(DataEntryType, Vec<u8>)> where R: std::io::Read + std::io::Seek {
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut typ = [0u8; 1];
+        reader.read_exact(&mut typ)?;
+        let typ = DataEntryType::new(typ[0])
+            .ok_or(WikitError::new(format!("unknown data entry type {:#x} at {:#x}", typ[0], offset)))?;
+        let mut sz = [0u8; 4];
+        reader.read_exact(&mut sz)?;
+        let mut buf = vec![0u8; u32::from_be_bytes(sz) as usize];
+        reader.read_exact(&mut buf)?;
+        Ok((typ, buf))
+    }
+}
+
+/// DataBlock groups several `DataEntry` and compresses them as a whole, its layout is
+///
+///      packtype:1
+///      packsz:4
+///      unpacksz:4
+///      data:packsz
+///
+struct DataBlock {
+    packtype: DataBlockCompression,
+    buf: Vec<u8>,
+}
+
+impl DataBlock {
+    fn new(packtype: DataBlockCompression) -> Self {
+        Self { packtype, buf: vec![] }
+    }
+
+    /// Append entry into the block and return its index value, the block is written out first if
+    /// it is full.
+    fn push<W>(&mut self, entry: &DataEntry, writer: &mut W) -> WikitResult<u64> where W: std::io::Write + std::io::Seek {
+        if self.buf.len() > 0 && self.buf.len() + entry.size() > MAX_DATA_BLOCK_SIZE {
+            self.flush(writer)?;
+        }
+        // the block will be written at current position when it is flushed
+        let block_offset = writer.seek(SeekFrom::Current(0))?;
+        let mut cursor = Cursor::new(&mut self.buf);
+        cursor.seek(SeekFrom::End(0))?;
+        let (entry_offset, _count) = entry.write(&mut cursor)?;
+        Ok(block_offset << DATA_BLOCK_OFFSET_SHIFT | entry_offset)
+    }
+
+    fn flush<W>(&mut self, writer: &mut W) -> WikitResult<()> where W: std::io::Write + std::io::Seek {
+        if self.buf.len() == 0 {
+            return Ok(());
+        }
+        let packed = match self.packtype {
+            DataBlockCompression::None => self.buf.clone(),
+            DataBlockCompression::Zlib => {
+                let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::best());
+                encoder.write_all(&self.buf[..])?;
+                encoder.finish()?
+            },
+            DataBlockCompression::Zstd => zstd::stream::encode_all(&self.buf[..], 19)?,
+        };
+        writer.write(&[self.packtype as u8])?;
+        writer.write(&(packed.len() as u32).to_be_bytes()[..])?;
+        writer.write(&(self.buf.len() as u32).to_be_bytes()[..])?;
+        writer.write(&packed[..])?;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Read the block at `offset` and return its uncompressed content
+    fn read<R>(reader: &mut R, offset: u64) -> WikitResult<Vec<u8>> where R: std::io::Read + std::io::Seek {
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut packtype = [0u8; 1];
+        reader.read_exact(&mut packtype)?;
+        let packtype = DataBlockCompression::new(packtype[0])
+            .ok_or(WikitError::new(format!("unknown data block compression {:#x} at {:#x}", packtype[0], offset)))?;
+        let mut packsz = [0u8; 4];
+        reader.read_exact(&mut packsz)?;
+        let mut unpacksz = [0u8; 4];
+        reader.read_exact(&mut unpacksz)?;
+        let unpacksz = u32::from_be_bytes(unpacksz) as usize;
+        let mut packed = vec![0u8; u32::from_be_bytes(packsz) as usize];
+        reader.read_exact(&mut packed)?;
+        let buf = match packtype {
+            DataBlockCompression::None => packed,
+            DataBlockCompression::Zlib => {
+                let mut buf = Vec::with_capacity(unpacksz);
+                zlib::Decoder::new(&packed[..]).read_to_end(&mut buf)?;
+                buf
+            },
+            DataBlockCompression::Zstd => zstd::stream::decode_all(&packed[..])?,
+        };
+        if buf.len() != unpacksz {
+            return Err(WikitError::new(format!("data block at {:#x} is broken", offset)));
+        }
+        Ok(buf)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct WikitHead {
+    // format version
+    pub version: u32,
     // dictionary standard name
     pub name: String,
     // detail description of dictionary
@@ -100,11 +460,188 @@ pub struct WikitHead {
     pub script: String,
     // css style
     pub style: String,
+    // resource index offset from file start, zero if there is no resource
+    pub ribase: u64,
+    // resource index size
+    pub risz: u64,
+    // full-text postings offset from file start, the following full-text fields are zero before
+    // version 0x04 or if full-text index is not built
+    pub pbase: u64,
+    // full-text postings size
+    pub psz: u64,
+    // full-text index offset from file start
+    pub fibase: u64,
+    // full-text index size
+    pub fisz: u64,
+    // normalization of index keys, it is always `Lowercase` before version 0x05
+    pub knorm: index::KeyNormalization,
+    // checksum offset from file start, the following checksum fields are zero before version 0x03
+    pub cbase: u64,
+    // checksum size
+    pub csz: u64,
+    // checksum of checksum section
+    pub csum: u32,
+    // the size of section chunk covered by one checksum
+    pub chunksz: u32,
+    // checksum of header
+    pub hdrsum: u32,
 }
 
 impl WikitHead {
-    pub fn new(headbuf: &[u8]) -> WikitResult<Self> {
-        let r: NomResult<WikitHead> = do_parse!(headbuf,
+    /// Parse header which follows `hdrsz` field with the decoder of given format `version`
+    pub fn new(version: u32, headbuf: &[u8]) -> WikitResult<Self> {
+        let r = match version {
+            0x01 => Self::decode_v1(headbuf),
+            0x02 => Self::decode_v2(headbuf),
+            0x03 => Self::decode_v3(headbuf),
+            0x04 => Self::decode_v4(headbuf),
+            0x05 => Self::decode_v5(headbuf),
+            _ => return Err(WikitError::new(format!("unsupported wikit version {:#x}", version))),
+        };
+
+        match r {
+            Ok(r) => Ok(r.1),
+            Err(e) => {
+                Err(WikitError::new(format!("failed to parse WikitHead of version {:#x}: {:?}", version, e)))
+            }
+        }
+    }
+
+    // dictionaries created before resources are supported do not have `ribase` and `risz` fields
+    fn decode_v1(headbuf: &[u8]) -> NomResult<'_, WikitHead> {
+        do_parse!(headbuf,
+            head: call!(Self::decode_common) >>
+            rindex: opt!(complete!(tuple!(be_u64, be_u64))) >>
+            (
+                WikitHead {
+                    version: 0x01,
+                    ribase: rindex.map(|x| x.0).unwrap_or(0),
+                    risz: rindex.map(|x| x.1).unwrap_or(0),
+                    ..head
+                }
+            )
+        )
+    }
+
+    fn decode_v2(headbuf: &[u8]) -> NomResult<'_, WikitHead> {
+        do_parse!(headbuf,
+            head: call!(Self::decode_common) >>
+            ribase: be_u64 >>
+            risz: be_u64 >>
+            (
+                WikitHead {
+                    version: 0x02,
+                    ribase,
+                    risz,
+                    ..head
+                }
+            )
+        )
+    }
+
+    fn decode_v3(headbuf: &[u8]) -> NomResult<'_, WikitHead> {
+        do_parse!(headbuf,
+            head: call!(Self::decode_common) >>
+            ribase: be_u64 >>
+            risz: be_u64 >>
+            cbase: be_u64 >>
+            csz: be_u64 >>
+            csum: be_u32 >>
+            chunksz: be_u32 >>
+            hdrsum: be_u32 >>
+            (
+                WikitHead {
+                    version: 0x03,
+                    ribase,
+                    risz,
+                    cbase,
+                    csz,
+                    csum,
+                    chunksz,
+                    hdrsum,
+                    ..head
+                }
+            )
+        )
+    }
+
+    fn decode_v4(headbuf: &[u8]) -> NomResult<'_, WikitHead> {
+        do_parse!(headbuf,
+            head: call!(Self::decode_common) >>
+            ribase: be_u64 >>
+            risz: be_u64 >>
+            pbase: be_u64 >>
+            psz: be_u64 >>
+            fibase: be_u64 >>
+            fisz: be_u64 >>
+            cbase: be_u64 >>
+            csz: be_u64 >>
+            csum: be_u32 >>
+            chunksz: be_u32 >>
+            hdrsum: be_u32 >>
+            (
+                WikitHead {
+                    version: 0x04,
+                    ribase,
+                    risz,
+                    pbase,
+                    psz,
+                    fibase,
+                    fisz,
+                    cbase,
+                    csz,
+                    csum,
+                    chunksz,
+                    hdrsum,
+                    ..head
+                }
+            )
+        )
+    }
+
+    fn decode_v5(headbuf: &[u8]) -> NomResult<'_, WikitHead> {
+        do_parse!(headbuf,
+            head: call!(Self::decode_common) >>
+            ribase: be_u64 >>
+            risz: be_u64 >>
+            pbase: be_u64 >>
+            psz: be_u64 >>
+            fibase: be_u64 >>
+            fisz: be_u64 >>
+            knorm: map_res!(take!(1),
+                |x: &[u8]| -> AnyResult<index::KeyNormalization> {
+                    index::KeyNormalization::new(x[0]).ok_or(anyhow::anyhow!("unknown key normalization"))
+                }
+            ) >>
+            cbase: be_u64 >>
+            csz: be_u64 >>
+            csum: be_u32 >>
+            chunksz: be_u32 >>
+            hdrsum: be_u32 >>
+            (
+                WikitHead {
+                    version: 0x05,
+                    ribase,
+                    risz,
+                    pbase,
+                    psz,
+                    fibase,
+                    fisz,
+                    knorm,
+                    cbase,
+                    csz,
+                    csum,
+                    chunksz,
+                    hdrsum,
+                    ..head
+                }
+            )
+        )
+    }
+
+    // fields shared by all versions
+    fn decode_common(headbuf: &[u8]) -> NomResult<'_, WikitHead> {
+        do_parse!(headbuf,
             namesz: be_u16 >>
             name: map_res!(take!(namesz),
                 |x: &[u8]| -> AnyResult<String> {
@@ -146,6 +683,7 @@ impl WikitHead {
             ) >>
             (
                 WikitHead {
+                    version: 0,
                     name,
                     desc,
                     ifmt,
@@ -155,16 +693,21 @@ impl WikitHead {
                     dsz,
                     script,
                     style,
+                    ribase: 0,
+                    risz: 0,
+                    pbase: 0,
+                    psz: 0,
+                    fibase: 0,
+                    fisz: 0,
+                    knorm: index::KeyNormalization::Lowercase,
+                    cbase: 0,
+                    csz: 0,
+                    csum: 0,
+                    chunksz: 0,
+                    hdrsum: 0,
                 }
             )
-        );
-
-        match r {
-            Ok(r) => Ok(r.1),
-            Err(e) => {
-                Err(WikitError::new(format!("failed to parse WikitHead: {:?}", e)))
-            }
-        }
+        )
     }
 }
 
@@ -172,6 +715,7 @@ impl WikitHead {
 pub enum WikitDictionary {
     Local(LocalDictionary),
     Remote(RemoteDictionary),
+    Mdx(mdict::MdxDictionary),
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -192,9 +736,21 @@ impl RemoteDictionary {
         Ok(r)
     }
 
-    pub fn lookup<P>(&self, word: P, dict: P) -> WikitResult<Vec<(String, String)>> where P: AsRef<str> {
+    pub fn lookup<P>(&self, word: P, dict: P, options: &index::LookupOptions) -> WikitResult<Vec<(String, String)>> where P: AsRef<str> {
+        let mut url = format!(
+            "{}/wikit/query?word={}&dictname={}&limit={}&exact={}&transpose={}",
+            self.url, word.as_ref(), dict.as_ref(), options.limit, options.exact, options.transpose,
+        );
+        if let Some(distance) = options.max_distance {
+            url.push_str(&format!("&distance={}", distance));
+        }
+        let r = reqwest::blocking::get(url)?.json::<Vec<(String, String)>>()?;
+        Ok(r)
+    }
+
+    pub fn prefix<P>(&self, prefix: P, dict: P, limit: usize) -> WikitResult<Vec<(String, String)>> where P: AsRef<str> {
         let r = reqwest::blocking::get(
-            format!("{}/wikit/query?word={}&dictname={}", self.url, word.as_ref(), dict.as_ref())
+            format!("{}/wikit/prefix?word={}&dictname={}&limit={}", self.url, prefix.as_ref(), dict.as_ref(), limit)
         )?.json::<Vec<(String, String)>>()?;
         Ok(r)
     }
@@ -225,9 +781,9 @@ impl RemoteDictionary {
 ///      magic:8
 ///      version:4
 ///
-/// if version is 0x01, then the follwoing layout is
+/// if version is 0x01, 0x02, 0x03, 0x04 or 0x05, then the follwoing layout is
 ///
-///      hdrsz:4
+///      hdrsz:4 (the header end offset from file start)
 ///      namesz:2
 ///      name:namesz
 ///      descsz:2
@@ -241,16 +797,85 @@ impl RemoteDictionary {
 ///      script: scriptsz
 ///      stylesz: 4
 ///      style: stylesz
+///      ribase: 8
+///      risz: 8
+///      pbase: 8
+///      psz: 8
+///      fibase: 8
+///      fisz: 8
+///      knorm: 1
+///      cbase: 8
+///      csz: 8
+///      csum: 4
+///      chunksz: 4
+///      hdrsum: 4
 ///
 ///      data: dsz
 ///      index: isz
+///      resource index: risz
+///      full-text postings: psz
+///      full-text index: fisz
+///      checksum: csz
+///
+/// The data section is a list of `DataEntry` holding word meanings followed by media resources.
+/// Words are indexed by `index` and resources are indexed by `resource index` using their path
+/// relative to the media directory, such as `audio/hello.ogg`. The `ribase` and `risz` fields
+/// are absent in version 0x01 dictionaries created before resources are supported.
 ///
+/// In version 0x01, the data section is a plain list of `DataEntry` and the index value is the
+/// offset of `DataEntry` from file start.
+///
+/// In version 0x02, entries are grouped into compressed `DataBlock` and the index value is
+/// `block_offset << 24 | entry_offset` where `block_offset` is the offset of `DataBlock` from
+/// file start, and `entry_offset` is the offset of `DataEntry` in the uncompressed block.
+///
+/// Version 0x03 adds fields from `cbase` to `hdrsum` which are absent in older versions. The
+/// checksum section is a list of big endian adler32 checksums of every `chunksz` bytes of data,
+/// index and resource index section in order, `csum` is the adler32 checksum of the checksum
+/// section itself and `hdrsum` is the adler32 checksum of all bytes before it.
+///
+/// Version 0x04 adds fields from `pbase` to `fisz` for the optional full-text index, see
+/// [fulltext::write] for the layout, they are zero if the full-text index is not built. The
+/// checksum section covers full-text postings and index after resource index.
+///
+/// Version 0x05 adds `knorm` which tells how words are normalized into index keys (see
+/// [index::KeyNormalization]), and words sharing the same key are stored in one `DataEntry` of
+/// type `WORDS` whose content is
+///
+///      count:2
+///      wordsz:2 (the following four fields are repeated count times)
+///      word:wordsz
+///      meaningsz:4
+///      meaning:meaningsz
+///
+/// where `word` is the original headword for display. Before version 0x05, the index key is the
+/// headword itself and the entry content is the meaning.
+///
+/// The whole file is memory mapped once when loaded and shared by the indexes and all lookups,
+/// and it is (de)serialized as its path.
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "PathBuf", into = "PathBuf")]
 pub struct LocalDictionary {
     pub head: WikitHead,
     // local path of dictionary
     pub path: PathBuf,
+    mmap: Arc<Mmap>,
     idx: index::FSTIndex,
+    ridx: Option<index::FSTIndex>,
+    fidx: Option<index::FSTIndex>,
+}
+
+impl TryFrom<PathBuf> for LocalDictionary {
+    type Error = WikitError;
+    fn try_from(path: PathBuf) -> WikitResult<Self> {
+        Self::load(path)
+    }
+}
+
+impl From<LocalDictionary> for PathBuf {
+    fn from(dict: LocalDictionary) -> Self {
+        dict.path
+    }
 }
 
 impl LocalDictionary {
@@ -262,7 +887,21 @@ impl LocalDictionary {
     ///
     /// Moreover, you can provide a file named `dict.toml` alonside with your dictionary such as
     /// `/some/dir/dict.toml` to describe your dictionary, see [WikitDictProfile] for more details.
+    ///
+    /// Media resources (images, audios and so on) found in directory `/some/dir/dict.media` are
+    /// packed into the dictionary too, you can change the directory by `media` of the profile.
+    /// For mdx source, resources of `/some/dir/dict.mdd` are packed as well if it exists.
     pub fn create<P, Q>(srcfile: P, outfile: Option<Q>) -> WikitResult<PathBuf>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>
+    {
+        Self::create_with_policy(srcfile, outfile, None)
+    }
+
+    /// Same as [LocalDictionary::create] but words with multiple definitions are handled by
+    /// `policy` instead of `duplicate` of the profile if it is given
+    pub fn create_with_policy<P, Q>(srcfile: P, outfile: Option<Q>, policy: Option<&util::DuplicatePolicy>) -> WikitResult<PathBuf>
     where
         P: AsRef<Path>,
         Q: AsRef<Path>
@@ -271,34 +910,12 @@ impl LocalDictionary {
         let (pdir, stem, suffix) = util::parse_path(srcfile)
             .context(elog!("failed to get parent directory of {}", srcfile.display()))?;
 
-        let mut conf = String::new();
-        if let Ok(mut f) = File::open(pdir.join(stem.clone() + ".toml")) {
-            f.read_to_string(&mut conf)?;
-        };
-        let conf = toml::from_str::<WikitDictProfile>(&conf).unwrap_or({
-            let mut def = WikitDictProfile::default();
-            def.name = stem.as_str().to_owned();
-            def.description = "this dictionary has no description".to_owned();
-            def.authors = vec!["anonymous".to_owned()];
-            def
-        });
-
-        let read_include_file = |maybe_path: &str| -> String {
-            let mut content = String::new();
-            if maybe_path.trim().starts_with('@') {
-                // TODO: avoid path traversal?
-                if let Ok(mut f) = File::open(pdir.join(&maybe_path.trim()[1..])) {
-                    _ = f.read_to_string(&mut content);
-                }
-            } else {
-                if maybe_path.trim().len() > 0 {
-                    content = maybe_path.to_owned();
-                }
-            }
-            content
-        };
-        let style = read_include_file(conf.css.trim());
-        let script = read_include_file(conf.js.trim());
+        let conf = WikitDictProfile::load(srcfile)?;
+        let style = conf.style();
+        let script = conf.script();
+        let compression = DataBlockCompression::from_name(&conf.compression)
+            .ok_or(WikitError::new(format!("unknown compression: {}", conf.compression)))?;
+        let mediadir = conf.media_dir(srcfile);
 
         let outfile = if let Some(outfile) = outfile {
             let outfile = outfile.as_ref();
@@ -310,91 +927,163 @@ impl LocalDictionary {
             pdir.join(conf.name.clone() + ".wikit")
         };
 
-        let mut writer = BufWriter::new(File::create(&outfile)?);
-        // magic
-        writer.write(WIKIT_MAGIC.as_bytes())?;
-        writer.write(&LATEST_WIKIT_FMT_VERSION.to_be_bytes()[..])?;
-        // hdrsz
-        let hdrsz_pos = writer.seek(SeekFrom::Current(0))?;
-        writer.seek(SeekFrom::Current(4))?;
-        // namesz and name
-        let namesz = conf.name.len() as u16;
-        writer.write(&namesz.to_be_bytes()[..])?;
-        writer.write(&conf.name.as_bytes()[..])?;
-        // descsz and desc
-        let descsz = conf.description.len() as u16;
-        writer.write(&descsz.to_be_bytes()[..])?;
-        writer.write(&conf.description.as_bytes()[..])?;
-        // ifmt
-        writer.write(&[index::IndexFormat::FST as u8])?;
-        // ibase
-        let ibase_pos = writer.seek(SeekFrom::Current(0))?;
-        writer.seek(SeekFrom::Current(8))?;
-        // isz
-        let isz_pos = writer.seek(SeekFrom::Current(0))?;
-        writer.seek(SeekFrom::Current(8))?;
-        // dbase
-        let dbase_pos = writer.seek(SeekFrom::Current(0))?;
-        writer.seek(SeekFrom::Current(8))?;
-        // dsz
-        let dsz_pos = writer.seek(SeekFrom::Current(0))?;
-        writer.seek(SeekFrom::Current(8))?;
-        // scriptsz and script
-        let scriptsz = script.len() as u32;
-        writer.write(&scriptsz.to_be_bytes()[..])?;
-        writer.write(&script.as_bytes()[..])?;
-        // stylesz and style
-        let stylesz = style.len() as u32;
-        writer.write(&stylesz.to_be_bytes()[..])?;
-        writer.write(&style.as_bytes()[..])?;
-
-        // save header size
-        let hdrsz = writer.seek(SeekFrom::Current(0))? as u32;
-        writer.seek(SeekFrom::Start(hdrsz_pos))?;
-        writer.write(&hdrsz.to_be_bytes()[..])?;
-        writer.seek(SeekFrom::Start(hdrsz as u64))?;
-
         let srcfile_path_str = &format!("{}", srcfile.display());
-        let mut word_meaning_list = match suffix.to_lowercase().as_str() {
+        // meanings of mdx are read again from the source when they are written, only redirect
+        // entries are kept in memory
+        let mut mdx = None;
+        let word_meaning_list = match suffix.to_lowercase().as_str() {
             "mdx" => {
-                mdict::parse_mdx(srcfile_path_str, None)?.entries
+                let reader = mdict::MdxReader::open(srcfile)?;
+                let mut words = Vec::with_capacity(reader.len());
+                for (i, entry) in reader.entries().enumerate() {
+                    let (word, meaning) = entry?;
+                    if util::link_target(&meaning).is_some() {
+                        words.push((word, SourceMeaning::Text(meaning)));
+                    } else {
+                        words.push((word, SourceMeaning::Record(i)));
+                    }
+                }
+                mdx = Some(reader);
+                words
             },
             "txt" => {
                 let f = File::open(srcfile_path_str).context(elog!("failed to open {}", srcfile_path_str))?;
-                reader::MDXSource::new(f).collect::<Vec<(String, String)>>()
+                reader::MDXSource::new(f).map(|(word, meaning)| (word, SourceMeaning::Text(meaning))).collect()
             }
             _ => {
                 return Err(WikitError::new(format!("source type {} is not supported", srcfile.display())));
             }
         };
-        // sort word by ascending
+        let norm = if conf.keep_accents {
+            index::KeyNormalization::Nfkc
+        } else {
+            index::KeyNormalization::NfkcFold
+        };
+        // redirect entries such as `@@@LINK=otherword` are stored as aliases of their targets
+        let (word_meaning_list, aliases, broken) = util::resolve_links(word_meaning_list, norm);
+        report_links(&aliases, &broken);
+        // merge duplicate word, words of different case such as `Polish` and `polish` are kept
+        let policy = match policy {
+            Some(policy) => policy.clone(),
+            None => conf.duplicate_policy()?,
+        };
+        let (mut word_meaning_list, dupcnt) = util::group_duplicates(word_meaning_list, &policy);
+        // sort word by ascending, the sort is stable so the definitions of the same word are kept
+        // in their original order
         word_meaning_list.sort_by(|a, b| a.0.cmp(&b.0));
-        // remove duplicate word
-        word_meaning_list.dedup_by(|a, b| a.0.eq(&b.0));
+        // words are written in sorted order, keep recently used record blocks to avoid
+        // decompressing a block for each word
+        let mut cache = mdict::MdxBlockCache::new(MDX_BLOCK_CACHE_SIZE);
+        let mut read_meaning = |meanings: &Vec<SourceMeaning>| -> WikitResult<String> {
+            let mut texts = Vec::with_capacity(meanings.len());
+            for meaning in meanings {
+                match (meaning, mdx.as_ref()) {
+                    (SourceMeaning::Text(text), _) => texts.push(text.clone()),
+                    (SourceMeaning::Record(i), Some(reader)) => texts.push(reader.record(*i, &mut cache)?),
+                    (SourceMeaning::Record(i), None) => return Err(WikitError::new(format!("record {} has no source", i))),
+                }
+            }
+            Ok(policy.join(texts))
+        };
 
-        let dstart = writer.seek(SeekFrom::Current(0))?;
-        let mut index_table = vec![];
-        for (word, meaning) in word_meaning_list.iter() {
-            let entry = DataEntry::new(DataEntryType::TXT, meaning.len() as u32, meaning.as_bytes());
-            let (offset, _count) = entry.write(&mut writer)?;
-            index_table.push((word, offset));
+        // resources of `x.mdd` alongside with `x.mdx` are packed too, but files in media directory
+        // take precedence over them, resources are read one by one when they are written
+        let mut media = BTreeMap::new();
+        let mddfile = pdir.join(stem.clone() + ".mdd");
+        let mdd = if suffix.to_lowercase() == "mdx" && mddfile.exists() {
+            let mdd = mdict::MddReader::open(&mddfile)?;
+            for (i, name) in mdd.names().enumerate() {
+                media.insert(util::normalize_resource_path(name), MediaSource::Mdd(i));
+            }
+            Some(mdd)
+        } else {
+            None
+        };
+        for (name, path) in collect_media(&mediadir)? {
+            media.insert(name, MediaSource::File(path));
         }
-        let dend = writer.seek(SeekFrom::Current(0))?;
+        let mut mddcache = mdict::MdxBlockCache::new(MDX_BLOCK_CACHE_SIZE);
+        let mut resources = media.into_iter().map(|(name, source)| -> WikitResult<_> {
+            let typ = DataEntryType::from_path(&name);
+            let buf = match (source, mdd.as_ref()) {
+                (MediaSource::Mdd(i), Some(mdd)) => mdd.resource(i, &mut mddcache)?,
+                (MediaSource::Mdd(i), None) => return Err(WikitError::new(format!("resource {} has no source", i))),
+                (MediaSource::File(path), _) => {
+                    let mut buf = vec![];
+                    File::open(&path).context(elog!("failed to open {}", path.display()))?
+                        .read_to_end(&mut buf)?;
+                    buf
+                }
+            };
+            Ok((name, typ, buf))
+        });
 
-        let dbase = dstart as u64;
-        writer.seek(SeekFrom::Start(dbase_pos))?;
-        writer.write(&dbase.to_be_bytes()[..])?;
-        let dsz = (dend - dstart) as u64;
-        writer.seek(SeekFrom::Start(dsz_pos))?;
-        writer.write(&dsz.to_be_bytes()[..])?;
+        let writer = WikitWriter {
+            name: &conf.name,
+            desc: &conf.description,
+            script: &script,
+            style: &style,
+            compression,
+            fulltext: conf.fulltext,
+            norm,
+        };
+        writer.write(&outfile, &word_meaning_list, &mut read_meaning, &aliases, &mut resources)?;
+        if dupcnt > 0 {
+            println!("[+] {} words have multiple definitions which are handled by policy {:?}", dupcnt, policy);
+        }
 
-        writer.seek(SeekFrom::Start(dend))?;
-        let (ibase, isz) = index::FSTIndex::write(&mut index_table.iter(), &mut writer)?;
-        let (ibase, isz) = (ibase as u64, isz as u64);
-        writer.seek(SeekFrom::Start(ibase_pos))?;
-        writer.write(&ibase.to_be_bytes()[..])?;
-        writer.seek(SeekFrom::Start(isz_pos))?;
-        writer.write(&isz.to_be_bytes()[..])?;
+        Ok(outfile)
+    }
+
+    /// Rewrite wikit dictionary `path` of older format version into the latest format
+    ///
+    /// If `outfile` is none, the dictionary is upgraded in place, and nothing is done if it is
+    /// already of the latest version.
+    pub fn upgrade<P, Q>(path: P, outfile: Option<Q>) -> WikitResult<PathBuf>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let dict = Self::load(path)?;
+        let outfile = match outfile {
+            Some(outfile) => outfile.as_ref().to_path_buf(),
+            None => {
+                if dict.head.version == LATEST_WIKIT_FMT_VERSION {
+                    return Ok(path.to_path_buf());
+                }
+                path.to_path_buf()
+            }
+        };
+
+        let norm = match dict.head.knorm {
+            index::KeyNormalization::Nfkc => index::KeyNormalization::Nfkc,
+            _ => index::KeyNormalization::NfkcFold,
+        };
+        let word_meaning_list = dict.entries()?.collect::<WikitResult<Vec<(String, String)>>>()?;
+        let (word_meaning_list, aliases, broken) = util::resolve_links(word_meaning_list, norm);
+        report_links(&aliases, &broken);
+        let mut resources = dict.resources()?;
+
+        // write into a temporary file first since `outfile` may be the dictionary itself
+        let tmpfile = outfile.with_extension("wikit.tmp");
+        let writer = WikitWriter {
+            name: &dict.head.name,
+            desc: &dict.head.desc,
+            script: &dict.head.script,
+            style: &dict.head.style,
+            compression: DataBlockCompression::Zstd,
+            fulltext: dict.fidx.is_some(),
+            norm,
+        };
+        if let Err(e) = writer.write(&tmpfile, &word_meaning_list, &mut |meaning| Ok(meaning.clone()), &aliases, &mut resources) {
+            _ = std::fs::remove_file(&tmpfile);
+            return Err(e);
+        }
+        // release the memory map before replacing the dictionary file
+        drop(resources);
+        drop(dict);
+        std::fs::rename(&tmpfile, &outfile)?;
 
         Ok(outfile)
     }
@@ -402,8 +1091,44 @@ impl LocalDictionary {
     pub fn load<P>(path: P) -> WikitResult<Self> where P: AsRef<Path> {
         let path = path.as_ref();
         let mut file = File::open(path)?;
+        let wikit_head = Self::read_head(&mut file)?;
 
-        let mut magic = [0u8; WIKIT_MAGIC.len()];
+        let mmap = Arc::new(unsafe { Mmap::map(&file)? });
+        let load_index = |section: WikitSection, base: u64, sz: u64, norm: index::KeyNormalization| {
+            index::FSTIndex::new(mmap.clone(), base, sz, norm)
+                .map_err(|_| WikitError::Corrupted { section: section.to_string(), offset: base })
+        };
+        let idx = load_index(WikitSection::Index, wikit_head.ibase, wikit_head.isz, wikit_head.knorm)?;
+        let ridx = if wikit_head.risz > 0 {
+            Some(load_index(WikitSection::ResourceIndex, wikit_head.ribase, wikit_head.risz, index::KeyNormalization::None)?)
+        } else {
+            None
+        };
+        let fidx = if wikit_head.fisz > 0 {
+            if wikit_head.pbase.checked_add(wikit_head.psz).filter(|x| *x <= mmap.len() as u64).is_none() {
+                return Err(WikitError::Corrupted {
+                    section: WikitSection::Fulltext.to_string(),
+                    offset: wikit_head.pbase,
+                });
+            }
+            Some(load_index(WikitSection::FulltextIndex, wikit_head.fibase, wikit_head.fisz, index::KeyNormalization::None)?)
+        } else {
+            None
+        };
+
+        Ok(LocalDictionary {
+            head: wikit_head,
+            path: path.to_path_buf(),
+            mmap,
+            idx,
+            ridx,
+            fidx,
+        })
+    }
+
+    // Read header at the start of dictionary `file`
+    fn read_head(file: &mut File) -> WikitResult<WikitHead> {
+        let mut magic = [0u8; WIKIT_MAGIC.len()];
         file.read_exact(&mut magic)?;
         let magic = String::from_utf8(magic.to_vec())?;
         if magic != WIKIT_MAGIC {
@@ -413,41 +1138,58 @@ impl LocalDictionary {
         let mut version = [0u8; 4];
         file.read_exact(&mut version)?;
         let version = u32::from_be_bytes(version);
-        if version != LATEST_WIKIT_FMT_VERSION {
-            return Err(WikitError::new("Wrong wikit version"));
+        if version > LATEST_WIKIT_FMT_VERSION {
+            return Err(WikitError::new(format!(
+                "wikit version {:#x} is newer than the supported version {:#x}, please upgrade wikit",
+                version, LATEST_WIKIT_FMT_VERSION,
+            )));
         }
 
+        // hdrsz is the header end offset, which also counts magic, version and itself
         let mut hdrsz = [0u8; 4];
         file.read_exact(&mut hdrsz)?;
-        let hdrsz = u32::from_be_bytes(hdrsz) as usize;
+        let hdrsz = (u32::from_be_bytes(hdrsz) as usize)
+            .checked_sub(WIKIT_MAGIC.len() + 4 + 4)
+            .ok_or(WikitError::new("Wikit header is broken"))?;
 
         let hdrbuf = file.bytes().take(hdrsz).filter_map(Result::ok).collect::<Vec<u8>>();
         if hdrbuf.len() != hdrsz {
             return Err(WikitError::new("Wikit header is broken"));
         }
-        let wikit_head = WikitHead::new(&hdrbuf[..])?;
+        WikitHead::new(version, &hdrbuf[..])
+    }
 
-        Ok(LocalDictionary {
-            head: wikit_head.clone(),
-            path: path.to_path_buf(),
-            idx: index::FSTIndex::new(path.to_path_buf(), wikit_head.ibase, wikit_head.isz),
-        })
+    /// Number of index keys, where each alias is counted as a key
+    pub fn len(&self) -> usize {
+        self.idx.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.idx.is_empty()
+    }
+
+    /// Number of resources such as images and audios
+    pub fn resource_len(&self) -> usize {
+        self.ridx.as_ref().map(|x| x.len()).unwrap_or(0)
+    }
+
+    /// Whether full-text index is built
+    pub fn has_fulltext(&self) -> bool {
+        self.fidx.is_some()
     }
 
-    pub fn lookup<P>(&self, word: P) -> WikitResult<Vec<(String, String)>> where P: AsRef<str> {
-        if let Ok(poslist) = self.idx.lookup(word) {
-            let mut file = File::open(&self.path)?;
+    /// Lookup `word` and its similar words, see [index::LookupOptions] for the available options
+    pub fn lookup<P>(&self, word: P, options: &index::LookupOptions) -> WikitResult<Vec<(String, String)>> where P: AsRef<str> {
+        if let Ok(poslist) = self.idx.lookup(word, options) {
             let mut anslist = vec![];
-            for (word, offset) in poslist {
-                let file = std::io::Read::by_ref(&mut file);
-                // just ignore DataEntryType
-                file.seek(SeekFrom::Start(offset + 1))?;
-                let mut meaning_size = [0u8; 4];
-                file.read_exact(&mut meaning_size)?;
-                let meaning_size = u32::from_be_bytes(meaning_size) as usize;
-                let meaning_buf = file.bytes().take(meaning_size).filter_map(Result::ok).collect::<Vec<u8>>();
-                if meaning_buf.len() == meaning_size {
-                    anslist.push((word.to_string(), String::from_utf8(meaning_buf)?));
+            let mut seen = HashSet::new();
+            for (key, offset) in poslist {
+                // an alias shares the entry with its target
+                if !seen.insert(offset) {
+                    continue;
+                }
+                if let Ok(words) = self.read_words(key, offset) {
+                    anslist.extend(words);
                 }
             }
             return Ok(anslist);
@@ -455,6 +1197,355 @@ impl LocalDictionary {
         return Err(WikitError::new("No such word or similar words"));
     }
 
+    /// Get at most `limit` `(word, meaning)` pairs whose word starts with `prefix`, which is
+    /// useful for autocompletion
+    pub fn prefix<P>(&self, prefix: P, limit: usize) -> WikitResult<Vec<(String, String)>> where P: AsRef<str> {
+        self.read_meanings(self.idx.prefix(prefix, limit)?)
+    }
+
+    /// Get at most `limit` `(word, meaning)` pairs whose word matches `pattern` such as `c?t` of
+    /// [index::PatternSyntax::Glob] or `c.t` of [index::PatternSyntax::Regex]
+    pub fn pattern<P>(&self, pattern: P, syntax: index::PatternSyntax, limit: usize) -> WikitResult<Vec<(String, String)>> where P: AsRef<str> {
+        self.read_meanings(self.idx.pattern(pattern, syntax, limit)?)
+    }
+
+    /// Search at most `limit` words whose meaning contains all tokens of `query`
+    ///
+    /// Words whose meaning contains `query` as a whole are ranked first, the full-text index must
+    /// be enabled by `fulltext` of [WikitDictProfile] when the dictionary is created.
+    pub fn search_fulltext<S>(&self, query: S, limit: usize) -> WikitResult<Vec<fulltext::FulltextHit>> where S: AsRef<str> {
+        let fidx = self.fidx.as_ref().ok_or(WikitError::new("dictionary has no full-text index"))?;
+        let postings = fulltext::Postings::new(
+            &self.mmap[self.head.pbase as usize..(self.head.pbase + self.head.psz) as usize]
+        );
+        let tokens = fulltext::tokenize(query.as_ref());
+        if tokens.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut lists = vec![];
+        for token in tokens.iter() {
+            match fidx.get(token)? {
+                Some(offset) => lists.push(postings.list(offset)?),
+                None => return Ok(vec![]),
+            }
+        }
+
+        let (mut hits, mut partial_hits) = (vec![], vec![]);
+        for wordref in fulltext::intersect(lists) {
+            let (word, value) = postings.word(wordref)?;
+            let words = self.read_words(word.clone(), value)?;
+            let meaning = words.iter().find(|x| x.0 == word).or(words.first()).map(|x| x.1.as_str());
+            let text = fulltext::strip_html(meaning.unwrap_or_default());
+            let (snippet, phrase) = fulltext::snippet(text.as_str(), query.as_ref());
+            let hit = fulltext::FulltextHit { word, snippet };
+            if phrase {
+                hits.push(hit);
+                if hits.len() >= limit {
+                    break;
+                }
+            } else if partial_hits.len() < limit {
+                partial_hits.push(hit);
+            }
+        }
+        hits.extend(partial_hits);
+        hits.truncate(limit);
+        Ok(hits)
+    }
+
+    /// Get media resource content and its MIME type by resource path such as `audio/hello.ogg`
+    pub fn get_resource<P>(&self, path: P) -> WikitResult<(Vec<u8>, String)> where P: AsRef<str> {
+        let ridx = self.ridx.as_ref().ok_or(WikitError::new("dictionary has no resource"))?;
+        let path = util::normalize_resource_path(path);
+        let offset = ridx.get(&path)?
+            .ok_or(WikitError::new(format!("no such resource: {}", path)))?;
+        let (typ, buf) = self.read_entry(offset)?;
+        Ok((buf, typ.mime().to_string()))
+    }
+
+    /// Verify integrity of the dictionary file, see [LocalDictionary::verify_file]
+    pub fn verify(&self) -> WikitResult<()> {
+        Self::verify_file(&self.path)
+    }
+
+    /// Verify integrity of dictionary file `path` without loading it, so that a dictionary which
+    /// fails to load such as a truncated download can be checked too
+    ///
+    /// The first broken section and its offset from file start are returned as
+    /// [WikitError::Corrupted]. Dictionaries before version 0x03 have no checksums, so only the
+    /// section bounds and indexes are checked.
+    pub fn verify_file<P>(path: P) -> WikitResult<()> where P: AsRef<Path> {
+        let corrupted = |section: WikitSection, offset: u64| {
+            WikitError::Corrupted { section: section.to_string(), offset }
+        };
+        let mut file = File::open(path.as_ref())?;
+        let flen = file.metadata()?.len();
+
+        let mut prefix = [0u8; WIKIT_MAGIC.len() + 4 + 4];
+        if flen < prefix.len() as u64 {
+            return Err(corrupted(WikitSection::Header, flen));
+        }
+        file.read_exact(&mut prefix)?;
+        let hdrend = u32::from_be_bytes([prefix[12], prefix[13], prefix[14], prefix[15]]) as u64;
+        if hdrend > flen {
+            return Err(corrupted(WikitSection::Header, flen));
+        }
+        file.seek(SeekFrom::Start(0))?;
+        let head = Self::read_head(&mut file).map_err(|_| corrupted(WikitSection::Header, 0))?;
+        let has_checksum = head.version >= 0x03;
+        if has_checksum {
+            let mut hdrbuf = vec![0u8; hdrend.saturating_sub(4) as usize];
+            file.seek(SeekFrom::Start(0))?;
+            file.read_exact(&mut hdrbuf)?;
+            if adler32(&hdrbuf[..]) != head.hdrsum || head.chunksz == 0 {
+                return Err(corrupted(WikitSection::Header, 0));
+            }
+        }
+
+        let mut sections = vec![
+            (WikitSection::Data, head.dbase, head.dsz),
+            (WikitSection::Index, head.ibase, head.isz),
+            (WikitSection::ResourceIndex, head.ribase, head.risz),
+            (WikitSection::Fulltext, head.pbase, head.psz),
+            (WikitSection::FulltextIndex, head.fibase, head.fisz),
+        ];
+        if has_checksum {
+            sections.push((WikitSection::Checksum, head.cbase, head.csz));
+        }
+        check_section_bounds(&sections, flen)?;
+
+        if has_checksum {
+            let mut sumbuf = vec![0u8; head.csz as usize];
+            file.seek(SeekFrom::Start(head.cbase))?;
+            file.read_exact(&mut sumbuf)?;
+            if adler32(&sumbuf[..]) != head.csum {
+                return Err(corrupted(WikitSection::Checksum, head.cbase));
+            }
+            let mut sums = sumbuf.chunks_exact(4).map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]));
+            for (section, base, sz) in sections.iter().filter(|x| x.0 != WikitSection::Checksum) {
+                let chunksums = chunk_checksums(&mut file, *base, *sz, head.chunksz)?;
+                for (i, chunksum) in chunksums.into_iter().enumerate() {
+                    if sums.next() != Some(chunksum) {
+                        return Err(corrupted(*section, base + i as u64 * head.chunksz as u64));
+                    }
+                }
+            }
+        }
+
+        // all sections are in the file now
+        let mmap = Arc::new(unsafe { Mmap::map(&file)? });
+        let mut indexes = vec![(WikitSection::Index, head.ibase, head.isz)];
+        if head.risz > 0 {
+            indexes.push((WikitSection::ResourceIndex, head.ribase, head.risz));
+        }
+        for (section, base, sz) in indexes {
+            let valid = index::FSTIndex::new(mmap.clone(), base, sz, index::KeyNormalization::None)
+                .and_then(|idx| idx.verify());
+            if valid.is_err() {
+                return Err(corrupted(section, base));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Iterate all `(word, meaning)` pairs in the lexicographical order of index key
+    ///
+    /// An alias is yielded as a redirect entry `(alias, @@@LINK=target)`, where the alias is its
+    /// index key.
+    pub fn entries(&self) -> WikitResult<impl Iterator<Item = WikitResult<(String, String)>> + '_> {
+        let entries = DataEntries::new(self, self.idx.iter()?);
+        let norm = self.head.knorm;
+        Ok(entries.flat_map(move |entry| {
+            let words = entry.and_then(|(key, typ, buf)| {
+                let words = decode_words(key.clone(), typ, buf)?;
+                if words.iter().any(|(word, _)| norm.normalize(word) == key) {
+                    return Ok(words);
+                }
+                Ok(words.into_iter().take(1).map(|(word, _)| (key.clone(), format!("{}{}", util::LINK_MARKER, word))).collect())
+            });
+            match words {
+                Ok(words) => words.into_iter().map(Ok).collect::<Vec<_>>(),
+                Err(e) => vec![Err(e)],
+            }
+        }))
+    }
+
+    /// Iterate all `(resource path, resource type, resource content)` sorted by resource path
+    pub fn resources(&self) -> WikitResult<impl Iterator<Item = WikitResult<(String, DataEntryType, Vec<u8>)>> + '_> {
+        let keys = match self.ridx.as_ref() {
+            Some(ridx) => Some(ridx.iter()?),
+            None => None,
+        };
+        Ok(keys.map(|keys| DataEntries::new(self, keys)).into_iter().flatten())
+    }
+
+    /// Write dictionary into text file of `MDXSource` format
+    ///
+    /// A profile `x.toml` is written alongside with output `x.txt` and resources are extracted
+    /// into directory `x.media`, so the text file can be used to create wikit dictionary again.
+    pub fn write_into_text<P>(&self, output: P) -> WikitResult<()> where P: AsRef<Path> {
+        let output = output.as_ref();
+        let mut writer = BufWriter::new(File::create(output)?);
+        for entry in self.entries()? {
+            let (word, meaning) = entry?;
+            // we do not need the messy `^M` character
+            let meaning = meaning.trim_matches(char::from(0)).replace("\r\n", "\n");
+            writer.write_all(format!("{}\n{}\n</>\n\n", word, meaning.trim()).as_bytes())?;
+        }
+        writer.flush()?;
+        self.write_source_profile(output, true)
+    }
+
+    /// Write dictionary into text file of `WikitSource` format such as `x.wikit.txt`
+    ///
+    /// Dictionary information, script and style are written as `info`, `js` and `css` items
+    /// before `word` items, resources are extracted into directory `x.wikit.media`.
+    pub fn write_into_wikit_source<P>(&self, output: P) -> WikitResult<()> where P: AsRef<Path> {
+        let output = output.as_ref();
+        let mut writer = BufWriter::new(File::create(output)?);
+        let write_item = |writer: &mut BufWriter<File>, typ: &str, name: &str, mime: &str, body: &str| -> WikitResult<()> {
+            writer.write_all(b"(\n")?;
+            for (k, v) in [("type", typ), ("name", name), ("mime", mime)] {
+                writer.write_all(format!("    \"{}\": {},\n", k, serde_json::to_string(v).context(elog!("failed to quote {}", v))?).as_bytes())?;
+            }
+            writer.write_all(b") {\n")?;
+            for line in body.trim_matches(char::from(0)).replace("\r\n", "\n").trim().lines() {
+                if line.trim().len() > 0 {
+                    writer.write_all(format!("    {}\n", line.trim_end()).as_bytes())?;
+                } else {
+                    writer.write_all(b"\n")?;
+                }
+            }
+            writer.write_all(b"}\n\n")?;
+            Ok(())
+        };
+
+        let info = format!("desc = {}\n", toml::Value::String(self.head.desc.clone()));
+        write_item(&mut writer, "info", &self.head.name, "application/toml", &info)?;
+        if self.head.script.trim().len() > 0 {
+            write_item(&mut writer, "js", "script.js", "text/javascript", &self.head.script)?;
+        }
+        if self.head.style.trim().len() > 0 {
+            write_item(&mut writer, "css", "style.css", "text/css", &self.head.style)?;
+        }
+        for entry in self.entries()? {
+            let (word, meaning) = entry?;
+            write_item(&mut writer, "word", &word, "text/html", &meaning)?;
+        }
+        writer.flush()?;
+        self.write_source_profile(output, false)
+    }
+
+    /// Write dictionary into MDX file with `options`, the style and script are written alongside as
+    /// `x.css` and `x.js` while other resources are not included
+    pub fn write_into_mdx<P>(&self, output: P, options: &mdict::MdxWriteOptions) -> WikitResult<()> where P: AsRef<Path> {
+        let tmpdir = tempfile::tempdir()?;
+        let textpath = tmpdir.path().join("mdx.txt");
+        let mut writer = BufWriter::new(File::create(&textpath)?);
+        for entry in self.entries()? {
+            let (word, meaning) = entry?;
+            let meaning = meaning.trim_matches(char::from(0)).replace("\r\n", "\n");
+            writer.write_all(format!("{}\n{}\n</>\n", word, meaning.trim()).as_bytes())?;
+        }
+        writer.flush()?;
+        mdict::create_mdx(&self.profile(), textpath.as_path(), output.as_ref(), options)?;
+        Ok(())
+    }
+
+    // Profile of the dictionary whose style and script are inlined
+    fn profile(&self) -> WikitDictProfile {
+        WikitDictProfile {
+            name: self.head.name.clone(),
+            description: self.head.desc.clone(),
+            css: self.head.style.clone(),
+            js: self.head.script.clone(),
+            ..Default::default()
+        }
+    }
+
+    // Write profile (only if `with_profile` is true) and resources for text output `x.txt`
+    fn write_source_profile(&self, output: &Path, with_profile: bool) -> WikitResult<()> {
+        let (pdir, stem, _) = util::parse_path(output)?;
+        if with_profile {
+            let profile = toml::to_string(&self.profile()).context(elog!("failed to serialize profile"))?;
+            std::fs::write(pdir.join(stem.clone() + ".toml"), profile)?;
+        }
+        let mediadir = pdir.join(stem + ".media");
+        for resource in self.resources()? {
+            let (name, _, buf) = resource?;
+            let path = mediadir.join(&name);
+            if !path.starts_with(&mediadir) || name.split('/').any(|x| x == "..") {
+                return Err(WikitError::new(format!("invalid resource path: {}", name)));
+            }
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(path, buf)?;
+        }
+        Ok(())
+    }
+
+    // Read `(word, meaning)` list of `(index key, index value)` list
+    fn read_meanings(&self, poslist: Vec<(String, u64)>) -> WikitResult<Vec<(String, String)>> {
+        let mut anslist = vec![];
+        let mut seen = HashSet::new();
+        for (key, offset) in poslist {
+            // an alias shares the entry with its target
+            if seen.insert(offset) {
+                anslist.extend(self.read_words(key, offset)?);
+            }
+        }
+        Ok(anslist)
+    }
+
+    // Read `(word, meaning)` list of index `key` referred by index value, redirect entries
+    // (found in dictionaries created before aliases are supported) are followed
+    fn read_words(&self, key: String, value: u64) -> WikitResult<Vec<(String, String)>> {
+        let (typ, buf) = self.read_entry(value)?;
+        let words = decode_words(key, typ, buf)?;
+        let lookup = |target: &str| -> Option<(String, String)> {
+            let value = self.idx.get(target).ok()??;
+            let (typ, buf) = self.read_entry(value).ok()?;
+            let mut words = decode_words(target.to_string(), typ, buf).ok()?;
+            let pos = words.iter().position(|(word, _)| word == target).unwrap_or(0);
+            (pos < words.len()).then(|| words.swap_remove(pos))
+        };
+        Ok(words.into_iter().map(|(word, meaning)| {
+            match util::follow_links(word.clone(), meaning.clone(), lookup) {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    log::warn!("failed to follow link of {}: {}", word, e);
+                    (word, meaning)
+                }
+            }
+        }).collect())
+    }
+
+    // Read `DataEntry` referred by index value
+    fn read_entry(&self, value: u64) -> WikitResult<(DataEntryType, Vec<u8>)> {
+        self.read_entry_cached(value, &mut None)
+    }
+
+    // Read `DataEntry` and keep the last uncompressed block as `(block offset, block)` in `cache`
+    fn read_entry_cached(
+        &self,
+        value: u64,
+        cache: &mut Option<(u64, Vec<u8>)>,
+    ) -> WikitResult<(DataEntryType, Vec<u8>)> {
+        let mut file = Cursor::new(&self.mmap[..]);
+        if self.head.version == 0x01 {
+            return DataEntry::read(&mut file, value);
+        }
+        let block_offset = value >> DATA_BLOCK_OFFSET_SHIFT;
+        let entry_offset = value & ((1 << DATA_BLOCK_OFFSET_SHIFT) - 1);
+        match cache {
+            Some((offset, _)) if *offset == block_offset => {},
+            _ => *cache = Some((block_offset, DataBlock::read(&mut file, block_offset)?)),
+        }
+        let block = cache.as_ref().map(|x| &x.1[..]).unwrap_or_default();
+        DataEntry::read(&mut Cursor::new(block), entry_offset)
+    }
+
     pub fn get_script(&self) -> &str {
         &self.head.script
     }
@@ -464,12 +1555,374 @@ impl LocalDictionary {
     }
 }
 
+// WikitWriter writes dictionary of the latest format
+struct WikitWriter<'a> {
+    name: &'a str,
+    desc: &'a str,
+    script: &'a str,
+    style: &'a str,
+    compression: DataBlockCompression,
+    fulltext: bool,
+    norm: index::KeyNormalization,
+}
+
+impl<'a> WikitWriter<'a> {
+    /// `words` is a list of `(word, meaning)` which must be sorted by word, a word may occur more
+    /// than once if all of its definitions are kept,
+    /// `read` reads definition of a meaning in `words` when the word is written, so definitions
+    /// need not be kept in memory unless full-text index is built,
+    /// `aliases` is a list of `(alias, target)` where `target` is a word of `words`,
+    /// `resources` yields `(resource path, resource type, resource content)` sorted by path.
+    fn write<M>(
+        &self,
+        outfile: &Path,
+        words: &[(String, M)],
+        read: &mut dyn FnMut(&M) -> WikitResult<String>,
+        aliases: &[(String, String)],
+        resources: &mut dyn Iterator<Item = WikitResult<(String, DataEntryType, Vec<u8>)>>,
+    ) -> WikitResult<()> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(outfile)?;
+        let mut writer = BufWriter::new(file);
+        // magic
+        writer.write(WIKIT_MAGIC.as_bytes())?;
+        writer.write(&LATEST_WIKIT_FMT_VERSION.to_be_bytes()[..])?;
+        // hdrsz
+        let hdrsz_pos = writer.seek(SeekFrom::Current(0))?;
+        writer.seek(SeekFrom::Current(4))?;
+        // namesz and name
+        let namesz = self.name.len() as u16;
+        writer.write(&namesz.to_be_bytes()[..])?;
+        writer.write(&self.name.as_bytes()[..])?;
+        // descsz and desc
+        let descsz = self.desc.len() as u16;
+        writer.write(&descsz.to_be_bytes()[..])?;
+        writer.write(&self.desc.as_bytes()[..])?;
+        // ifmt
+        writer.write(&[index::IndexFormat::FST as u8])?;
+        // ibase
+        let ibase_pos = writer.seek(SeekFrom::Current(0))?;
+        writer.seek(SeekFrom::Current(8))?;
+        // isz
+        let isz_pos = writer.seek(SeekFrom::Current(0))?;
+        writer.seek(SeekFrom::Current(8))?;
+        // dbase
+        let dbase_pos = writer.seek(SeekFrom::Current(0))?;
+        writer.seek(SeekFrom::Current(8))?;
+        // dsz
+        let dsz_pos = writer.seek(SeekFrom::Current(0))?;
+        writer.seek(SeekFrom::Current(8))?;
+        // scriptsz and script
+        let scriptsz = self.script.len() as u32;
+        writer.write(&scriptsz.to_be_bytes()[..])?;
+        writer.write(&self.script.as_bytes()[..])?;
+        // stylesz and style
+        let stylesz = self.style.len() as u32;
+        writer.write(&stylesz.to_be_bytes()[..])?;
+        writer.write(&self.style.as_bytes()[..])?;
+        // ribase
+        let ribase_pos = writer.seek(SeekFrom::Current(0))?;
+        writer.seek(SeekFrom::Current(8))?;
+        // risz
+        let risz_pos = writer.seek(SeekFrom::Current(0))?;
+        writer.seek(SeekFrom::Current(8))?;
+        // pbase, psz, fibase and fisz
+        let pbase_pos = writer.seek(SeekFrom::Current(0))?;
+        writer.seek(SeekFrom::Current(8 * 4))?;
+        // knorm
+        writer.write(&[self.norm as u8])?;
+        // cbase, csz and csum
+        let cbase_pos = writer.seek(SeekFrom::Current(0))?;
+        writer.seek(SeekFrom::Current(8 + 8 + 4))?;
+        // chunksz
+        writer.write(&CHECKSUM_CHUNK_SIZE.to_be_bytes()[..])?;
+        // hdrsum
+        let hdrsum_pos = writer.seek(SeekFrom::Current(0))?;
+        writer.seek(SeekFrom::Current(4))?;
+
+        // save header size
+        let hdrsz = writer.seek(SeekFrom::Current(0))? as u32;
+        writer.seek(SeekFrom::Start(hdrsz_pos))?;
+        writer.write(&hdrsz.to_be_bytes()[..])?;
+        writer.seek(SeekFrom::Start(hdrsz as u64))?;
+
+        let dstart = writer.seek(SeekFrom::Current(0))?;
+        let mut block = DataBlock::new(self.compression);
+        // words are grouped by index key and the order of words in the same group is kept
+        let mut groups: BTreeMap<String, Vec<(&str, &M)>> = BTreeMap::new();
+        for (word, meaning) in words.iter() {
+            groups.entry(self.norm.normalize(word)).or_default().push((word, meaning));
+        }
+        let (mut index_table, mut fulltext_table) = (BTreeMap::new(), vec![]);
+        for (key, group) in groups.iter() {
+            let mut texts = Vec::with_capacity(group.len());
+            for (word, meaning) in group.iter() {
+                texts.push((*word, read(meaning)?));
+            }
+            let buf = encode_words(&texts.iter().map(|(word, text)| (*word, text.as_str())).collect::<Vec<_>>())?;
+            let entry = DataEntry::new(DataEntryType::WORDS, buf.len() as u32, &buf[..]);
+            let offset = block.push(&entry, &mut writer)?;
+            index_table.insert(key.clone(), offset);
+            if self.fulltext {
+                fulltext_table.extend(texts.into_iter().map(|(word, text)| (word.to_string(), text, offset)));
+            }
+        }
+        // an alias refers to the entry of its target, and it is ignored if its key is taken
+        for (alias, target) in aliases.iter() {
+            let key = self.norm.normalize(alias);
+            if index_table.contains_key(&key) {
+                continue;
+            }
+            if let Some(offset) = index_table.get(&self.norm.normalize(target)).copied() {
+                index_table.insert(key, offset);
+            }
+        }
+        let index_table = index_table.into_iter().collect::<Vec<_>>();
+        // resources are always started with a new block
+        block.flush(&mut writer)?;
+        let mut resource_table = vec![];
+        for resource in resources {
+            let (name, typ, buf) = resource?;
+            let entry = DataEntry::new(typ, buf.len() as u32, &buf[..]);
+            let offset = block.push(&entry, &mut writer)?;
+            resource_table.push((name, offset));
+        }
+        block.flush(&mut writer)?;
+        let dend = writer.seek(SeekFrom::Current(0))?;
+
+        let dbase = dstart as u64;
+        writer.seek(SeekFrom::Start(dbase_pos))?;
+        writer.write(&dbase.to_be_bytes()[..])?;
+        let dsz = (dend - dstart) as u64;
+        writer.seek(SeekFrom::Start(dsz_pos))?;
+        writer.write(&dsz.to_be_bytes()[..])?;
+
+        writer.seek(SeekFrom::Start(dend))?;
+        let (ibase, isz) = index::FSTIndex::write(&mut index_table.iter(), &mut writer)?;
+        let (ibase, isz) = (ibase as u64, isz as u64);
+        writer.seek(SeekFrom::Start(ibase_pos))?;
+        writer.write(&ibase.to_be_bytes()[..])?;
+        writer.seek(SeekFrom::Start(isz_pos))?;
+        writer.write(&isz.to_be_bytes()[..])?;
+
+        let (ribase, risz) = if resource_table.len() > 0 {
+            writer.seek(SeekFrom::Start(ibase + isz))?;
+            let (ribase, risz) = index::FSTIndex::write(&mut resource_table.iter(), &mut writer)?;
+            writer.seek(SeekFrom::Start(ribase_pos))?;
+            writer.write(&ribase.to_be_bytes()[..])?;
+            writer.seek(SeekFrom::Start(risz_pos))?;
+            writer.write(&risz.to_be_bytes()[..])?;
+            (ribase, risz)
+        } else {
+            (0, 0)
+        };
+
+        let (pbase, psz, fibase, fisz) = if self.fulltext {
+            writer.seek(SeekFrom::End(0))?;
+            let (pbase, psz, fibase, fisz) = fulltext::write(&fulltext_table, &mut writer)?;
+            writer.seek(SeekFrom::Start(pbase_pos))?;
+            for v in [pbase, psz, fibase, fisz] {
+                writer.write(&v.to_be_bytes()[..])?;
+            }
+            (pbase, psz, fibase, fisz)
+        } else {
+            (0, 0, 0, 0)
+        };
+
+        // checksums are calculated from what have been written
+        let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+        let mut checksum = vec![];
+        for (base, sz) in [(dbase, dsz), (ibase, isz), (ribase, risz), (pbase, psz), (fibase, fisz)] {
+            for chunksum in chunk_checksums(&mut file, base, sz, CHECKSUM_CHUNK_SIZE)? {
+                checksum.extend(chunksum.to_be_bytes());
+            }
+        }
+        let cbase = file.seek(SeekFrom::End(0))?;
+        file.write_all(&checksum[..])?;
+        file.seek(SeekFrom::Start(cbase_pos))?;
+        file.write_all(&cbase.to_be_bytes()[..])?;
+        file.write_all(&(checksum.len() as u64).to_be_bytes()[..])?;
+        file.write_all(&adler32(&checksum[..]).to_be_bytes()[..])?;
+
+        let mut hdrbuf = vec![0u8; hdrsum_pos as usize];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut hdrbuf)?;
+        file.seek(SeekFrom::Start(hdrsum_pos))?;
+        file.write_all(&adler32(&hdrbuf[..]).to_be_bytes()[..])?;
+        file.flush()?;
+
+        Ok(())
+    }
+}
+
+// Report aliases and broken links found in dictionary source
+fn report_links(aliases: &[(String, String)], broken: &[(String, util::LinkError)]) {
+    if aliases.len() > 0 {
+        println!("[+] {} redirect entries are stored as aliases", aliases.len());
+    }
+    for (word, e) in broken.iter() {
+        println!("[!] broken link of {} is dropped: {}", word, e);
+    }
+}
+
+fn adler32(buf: &[u8]) -> u32 {
+    let mut adler = Adler32::new();
+    adler.write_slice(buf);
+    adler.checksum()
+}
+
+// Check that `(section, base, size)` list are in file of `flen` bytes, the first one out of the
+// file is returned as corrupted
+fn check_section_bounds(sections: &[(WikitSection, u64, u64)], flen: u64) -> WikitResult<()> {
+    for (section, base, sz) in sections.iter() {
+        if base.checked_add(*sz).map_or(true, |end| end > flen) {
+            return Err(WikitError::Corrupted { section: section.to_string(), offset: std::cmp::max(*base, flen) });
+        }
+    }
+    Ok(())
+}
+
+// Get adler32 checksum of every `chunksz` bytes of section `[base, base + sz)`
+fn chunk_checksums<R>(reader: &mut R, base: u64, sz: u64, chunksz: u32) -> WikitResult<Vec<u32>>
+where
+    R: std::io::Read + std::io::Seek
+{
+    reader.seek(SeekFrom::Start(base))?;
+    let mut chunk = vec![0u8; chunksz as usize];
+    let (mut checksums, mut remain) = (vec![], sz);
+    while remain > 0 {
+        let n = std::cmp::min(remain, chunksz as u64) as usize;
+        reader.read_exact(&mut chunk[..n])?;
+        checksums.push(adler32(&chunk[..n]));
+        remain -= n as u64;
+    }
+    Ok(checksums)
+}
+
+// Encode `(word, meaning)` list into content of `DataEntry` of type `WORDS`
+fn encode_words(words: &[(&str, &str)]) -> WikitResult<Vec<u8>> {
+    let count = u16::try_from(words.len()).map_err(|_| WikitError::new("too many words share the same key"))?;
+    let mut buf = count.to_be_bytes().to_vec();
+    for (word, meaning) in words {
+        let wordsz = u16::try_from(word.len()).map_err(|_| WikitError::new(format!("word is too long: {}", word)))?;
+        buf.extend(wordsz.to_be_bytes());
+        buf.extend(word.as_bytes());
+        buf.extend((meaning.len() as u32).to_be_bytes());
+        buf.extend(meaning.as_bytes());
+    }
+    Ok(buf)
+}
+
+// Decode content of `DataEntry` referred by index `key` into `(word, meaning)` list
+fn decode_words(key: String, typ: DataEntryType, buf: Vec<u8>) -> WikitResult<Vec<(String, String)>> {
+    if typ != DataEntryType::WORDS {
+        return Ok(vec![(key, String::from_utf8(buf)?)]);
+    }
+    let broken = || WikitError::new(format!("words of {} are broken", key));
+    let mut cursor = Cursor::new(&buf[..]);
+    let mut read_bytes = |n: usize| -> WikitResult<Vec<u8>> {
+        if cursor.position() as usize + n > buf.len() {
+            return Err(broken());
+        }
+        let mut v = vec![0u8; n];
+        cursor.read_exact(&mut v).map_err(|_| broken())?;
+        Ok(v)
+    };
+    let count = u16::from_be_bytes(read_bytes(2)?.try_into().unwrap());
+    let mut words = vec![];
+    for _ in 0..count {
+        let wordsz = u16::from_be_bytes(read_bytes(2)?.try_into().unwrap());
+        let word = String::from_utf8(read_bytes(wordsz as usize)?)?;
+        let meaningsz = u32::from_be_bytes(read_bytes(4)?.try_into().unwrap());
+        let meaning = String::from_utf8(read_bytes(meaningsz as usize)?)?;
+        words.push((word, meaning));
+    }
+    Ok(words)
+}
+
+// Iterator over `(key, type, content)` of entries referred by index
+struct DataEntries<'a> {
+    dict: &'a LocalDictionary,
+    keys: index::FSTKeys<'a>,
+    cache: Option<(u64, Vec<u8>)>,
+}
+
+impl<'a> DataEntries<'a> {
+    fn new(dict: &'a LocalDictionary, keys: index::FSTKeys<'a>) -> Self {
+        Self { dict, keys, cache: None }
+    }
+}
+
+impl<'a> Iterator for DataEntries<'a> {
+    type Item = WikitResult<(String, DataEntryType, Vec<u8>)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = match self.keys.next()? {
+            Ok(kv) => kv,
+            Err(e) => return Some(Err(e)),
+        };
+        let entry = self.dict.read_entry_cached(value, &mut self.cache);
+        Some(entry.map(|(typ, buf)| (key, typ, buf)))
+    }
+}
+
+// Meaning of source entry, definition of mdx is read from its record when it is written
+enum SourceMeaning {
+    Text(String),
+    Record(usize),
+}
+
+impl AsRef<str> for SourceMeaning {
+    fn as_ref(&self) -> &str {
+        match self {
+            SourceMeaning::Text(text) => text,
+            SourceMeaning::Record(_) => "",
+        }
+    }
+}
+
+// Where the content of resource comes from
+enum MediaSource {
+    File(PathBuf),
+    // the `i`-th resource of mdd
+    Mdd(usize),
+}
+
+// Collect `(resource path, file path)` list from media directory sorted by resource path
+fn collect_media(mediadir: &Path) -> WikitResult<Vec<(String, PathBuf)>> {
+    let mut resources = vec![];
+    if !mediadir.is_dir() {
+        return Ok(resources);
+    }
+    let mut dirs = vec![mediadir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let name = path.strip_prefix(mediadir)
+                .context(elog!("{} is not in media directory", path.display()))?
+                .to_str()
+                .ok_or(WikitError::new(format!("invalid resource path {}", path.display())))?;
+            resources.push((util::normalize_resource_path(name), path.clone()));
+        }
+    }
+    resources.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(resources)
+}
+
 pub fn load_dictionary_from_uri<S>(uri: S) -> Option<WikitDictionary> where S: AsRef<str> {
     let uri = uri.as_ref();
     if let Ok(url) = url::Url::parse(uri) {
         match url.scheme() {
             "file" => {
                 if let Ok(dictpath) = url.to_file_path() {
+                    // mdx is looked up in place without conversion
+                    let is_mdx = dictpath.extension().and_then(|x| x.to_str())
+                        .map(|x| x.eq_ignore_ascii_case("mdx")).unwrap_or(false);
+                    if is_mdx {
+                        return mdict::MdxDictionary::load(dictpath).ok().map(WikitDictionary::Mdx);
+                    }
                     if let Ok(dict) = LocalDictionary::load(dictpath) {
                         return Some(WikitDictionary::Local(dict));
                     }
@@ -526,3 +1979,395 @@ pub fn load_client_dictionary() -> WikitResult<Vec<WikitDictionary>> {
     }
     Ok(dicts)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::wikit::{LocalDictionary, WikitSection, LATEST_WIKIT_FMT_VERSION, check_section_bounds};
+    use crate::error::WikitError;
+
+    #[test]
+    fn test_create_with_media() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::copy("test/demo.txt", dir.path().join("demo.txt")).unwrap();
+        let audiodir = dir.path().join("demo.media").join("audio");
+        std::fs::create_dir_all(&audiodir).unwrap();
+        std::fs::write(audiodir.join("a.ogg"), b"OggS").unwrap();
+
+        let outfile = LocalDictionary::create(dir.path().join("demo.txt"), None::<&str>).unwrap();
+        let dict = LocalDictionary::load(outfile).unwrap();
+        let (buf, mime) = dict.get_resource("/audio/a.ogg").unwrap();
+        assert_eq!(buf, b"OggS");
+        assert_eq!(mime, "audio/ogg");
+        assert!(dict.get_resource("audio/b.ogg").is_err());
+        let r = dict.lookup("a", &Default::default()).unwrap();
+        assert!(r.iter().any(|(w, m)| w == "a" && m.starts_with("89ab")));
+
+        let words = dict.entries().unwrap().map(|x| x.unwrap().0).collect::<Vec<String>>();
+        assert_eq!(words, vec!["a", "b", "c"]);
+
+        let textpath = dir.path().join("export").join("demo.txt");
+        std::fs::create_dir_all(textpath.parent().unwrap()).unwrap();
+        dict.write_into_text(&textpath).unwrap();
+        assert!(std::fs::read_to_string(&textpath).unwrap().starts_with("a\n89ab\n</>\n"));
+        let outfile = LocalDictionary::create(&textpath, None::<&str>).unwrap();
+        let exported = LocalDictionary::load(outfile).unwrap();
+        assert_eq!(exported.head.name, "demo");
+        assert_eq!(exported.get_resource("audio/a.ogg").unwrap().0, b"OggS");
+
+        let srcpath = dir.path().join("demo.wikit.txt");
+        dict.write_into_wikit_source(&srcpath).unwrap();
+        let items = crate::reader::WikitSource::new(std::fs::File::open(&srcpath).unwrap()).collect::<Vec<_>>();
+        assert_eq!(items[0].header.typ, "info");
+        assert_eq!(items.iter().filter(|x| x.header.typ == "word").count(), 3);
+    }
+
+    #[test]
+    fn test_share_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<LocalDictionary>();
+
+        let dict = std::sync::Arc::new(LocalDictionary::load("test/demo.v1.wikit").unwrap());
+        let handles = ["a", "b", "c"].iter().map(|word| {
+            let dict = dict.clone();
+            std::thread::spawn(move || dict.lookup(word, &Default::default()).unwrap().iter().any(|(w, _)| w == word))
+        }).collect::<Vec<_>>();
+        assert!(handles.into_iter().all(|h| h.join().unwrap()));
+    }
+
+    #[test]
+    fn test_lookup_options() {
+        use crate::index::LookupOptions;
+
+        let dir = tempfile::tempdir().unwrap();
+        let words = ["receive", "recieve", "relieve", "receiver", "deceive", "reprieve"];
+        let content = words.iter().map(|w| format!("{}\n{}\n</>\n", w, w)).collect::<String>();
+        std::fs::write(dir.path().join("fuzzy.txt"), content).unwrap();
+        let path = LocalDictionary::create(dir.path().join("fuzzy.txt"), None::<&str>).unwrap();
+        let dict = LocalDictionary::load(path).unwrap();
+
+        let lookup = |options: &LookupOptions| {
+            dict.lookup("receive", options).unwrap().into_iter().map(|(w, _)| w).collect::<Vec<_>>()
+        };
+        let options = LookupOptions { max_distance: Some(1), ..Default::default() };
+        assert_eq!(lookup(&options), vec!["receive", "deceive", "receiver"]);
+        let options = LookupOptions { max_distance: Some(1), transpose: true, ..Default::default() };
+        assert_eq!(lookup(&options), vec!["receive", "deceive", "receiver", "recieve"]);
+        let options = LookupOptions { max_distance: Some(2), exact: false, limit: 3, ..Default::default() };
+        assert_eq!(lookup(&options), vec!["deceive", "receiver", "recieve"]);
+        // a huge distance is clamped instead of overflowing
+        let options = LookupOptions { max_distance: Some(u32::MAX), exact: false, limit: 3, ..Default::default() };
+        assert_eq!(options.distance(), Some(crate::index::MAX_LOOKUP_DISTANCE));
+        assert_eq!(lookup(&options), vec!["deceive", "receiver", "recieve"]);
+    }
+
+    #[test]
+    fn test_normalized_key() {
+        use crate::index::LookupOptions;
+
+        let dir = tempfile::tempdir().unwrap();
+        let content = "café\n1\n</>\nresume\n2\n</>\nrésumé\n3\n</>\nnaïve\n4\n</>\n";
+        std::fs::write(dir.path().join("accent.txt"), content).unwrap();
+        let path = LocalDictionary::create(dir.path().join("accent.txt"), None::<&str>).unwrap();
+        let dict = LocalDictionary::load(path).unwrap();
+
+        let exact = LookupOptions { max_distance: Some(0), ..Default::default() };
+        let words = |dict: &LocalDictionary, word| {
+            dict.lookup(word, &exact).unwrap().into_iter().map(|(w, _)| w).collect::<Vec<_>>()
+        };
+        assert_eq!(words(&dict, "cafe"), vec!["café"]);
+        assert_eq!(words(&dict, "CAFÉ"), vec!["café"]);
+        assert_eq!(words(&dict, "resume"), vec!["resume", "résumé"]);
+        assert_eq!(words(&dict, "nai\u{308}ve"), vec!["naïve"]);
+        assert_eq!(dict.entries().unwrap().count(), 4);
+
+        // NFD headword is still found by NFC query if accents are kept
+        let content = "cafe\u{301}\n1\n</>\nresume\n2\n</>\n";
+        std::fs::write(dir.path().join("keep.txt"), content).unwrap();
+        let profile = concat!(
+            "name = \"keep\"\nversion = \"1.0\"\nauthors = []\ndistributors = []\n",
+            "description = \"\"\nhomepage = \"\"\ncss = \"\"\njs = \"\"\nkeep_accents = true\n",
+        );
+        std::fs::write(dir.path().join("keep.toml"), profile).unwrap();
+        let path = LocalDictionary::create(dir.path().join("keep.txt"), None::<&str>).unwrap();
+        let dict = LocalDictionary::load(path).unwrap();
+        assert_eq!(words(&dict, "café"), vec!["cafe\u{301}"]);
+        assert!(words(&dict, "cafe").is_empty());
+        assert!(words(&dict, "résumé").is_empty());
+    }
+
+    #[test]
+    fn test_keep_case() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "Paris\n1\n</>\nUSA\n2\n</>\nPolish\n3\n</>\npolish\n4\n</>\n";
+        std::fs::write(dir.path().join("case.txt"), content).unwrap();
+        let path = LocalDictionary::create(dir.path().join("case.txt"), None::<&str>).unwrap();
+        let dict = LocalDictionary::load(path).unwrap();
+
+        let exact = crate::index::LookupOptions { max_distance: Some(0), ..Default::default() };
+        let r = dict.lookup("paris", &exact).unwrap();
+        assert!(r.len() == 1 && r[0].0 == "Paris" && r[0].1.starts_with("1"));
+        let r = dict.lookup("usa", &exact).unwrap();
+        assert_eq!(r[0].0, "USA");
+        let r = dict.lookup("Polish", &exact).unwrap();
+        assert_eq!(r.len(), 2);
+        assert!(r.iter().any(|(w, m)| w == "Polish" && m.starts_with("3")));
+        assert!(r.iter().any(|(w, m)| w == "polish" && m.starts_with("4")));
+        assert_eq!(dict.entries().unwrap().count(), 4);
+    }
+
+    #[test]
+    fn test_duplicate_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "bank\nriver side\n</>\nbank\nfinancial institution\n</>\ncat\nanimal\n</>\n";
+        let exact = crate::index::LookupOptions { max_distance: Some(0), ..Default::default() };
+        for (policy, expected) in [
+            ("first", vec!["river side"]),
+            ("last", vec!["financial institution"]),
+            ("concat", vec!["river side<hr>financial institution"]),
+            ("all", vec!["river side", "financial institution"]),
+        ] {
+            std::fs::write(dir.path().join("dup.txt"), content).unwrap();
+            let profile = format!(concat!(
+                "name = \"dup\"\nversion = \"1.0\"\nauthors = []\ndistributors = []\n",
+                "description = \"\"\nhomepage = \"\"\ncss = \"\"\njs = \"\"\nduplicate = \"{}\"\n",
+            ), policy);
+            std::fs::write(dir.path().join("dup.toml"), profile).unwrap();
+            let path = LocalDictionary::create(dir.path().join("dup.txt"), None::<&str>).unwrap();
+            let dict = LocalDictionary::load(path).unwrap();
+            let meanings = dict.lookup("bank", &exact).unwrap().into_iter()
+                .map(|(_, m)| m.trim_matches(char::from(0)).to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(meanings, expected, "policy {}", policy);
+        }
+
+        // the given policy overrides the one of the profile which is `all` now
+        let policy = crate::util::DuplicatePolicy::from_name("concat", Some(" | ")).unwrap();
+        let path = LocalDictionary::create_with_policy(dir.path().join("dup.txt"), None::<&str>, Some(&policy)).unwrap();
+        let dict = LocalDictionary::load(path).unwrap();
+        let r = dict.lookup("bank", &exact).unwrap();
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].1.trim_matches(char::from(0)), "river side | financial institution");
+    }
+
+    #[test]
+    fn test_profile() {
+        use crate::wikit::WikitDictProfile;
+
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["none.txt", "partial.txt", "broken.txt"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        let profile = WikitDictProfile::load(dir.path().join("none.txt")).unwrap();
+        assert_eq!(profile.name(), "none");
+
+        std::fs::write(dir.path().join("partial.toml"), "duplicate = \"concat\"\nfulltext = true\n").unwrap();
+        let profile = WikitDictProfile::load(dir.path().join("partial.txt")).unwrap();
+        assert_eq!(profile.name(), "partial");
+        assert_eq!(profile.duplicate, "concat");
+        assert!(profile.fulltext);
+
+        std::fs::write(dir.path().join("broken.toml"), "fulltext = yes\n").unwrap();
+        let e = WikitDictProfile::load(dir.path().join("broken.txt")).unwrap_err();
+        assert!(e.to_string().contains("broken.toml"));
+    }
+
+    #[test]
+    fn test_create_from_mdx() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "bank\nriver side\n</>\ncat\nanimal\n</>\nbank\nfinancial institution\n</>\nkitty\n@@@LINK=cat\n</>\n";
+        std::fs::write(dir.path().join("src.txt"), content).unwrap();
+        let profile = crate::wikit::WikitDictProfile::load(dir.path().join("src.txt")).unwrap();
+        let mdxpath = dir.path().join("dup.mdx");
+        crate::mdict::create_mdx(&profile, &dir.path().join("src.txt"), &mdxpath, &Default::default()).unwrap();
+        std::fs::write(dir.path().join("dup.toml"), concat!(
+            "name = \"dup\"\nversion = \"1.0\"\nauthors = []\ndistributors = []\n",
+            "description = \"\"\nhomepage = \"\"\ncss = \"\"\njs = \"\"\nduplicate = \"concat\"\n",
+        )).unwrap();
+        let mediadir = dir.path().join("media");
+        std::fs::create_dir_all(mediadir.join("img")).unwrap();
+        std::fs::write(mediadir.join("img").join("cat.png"), b"cat").unwrap();
+        std::fs::write(mediadir.join("b.mp3"), b"b").unwrap();
+        crate::mdict::create_mdd(&mediadir, &dir.path().join("dup.mdd")).unwrap();
+
+        let path = LocalDictionary::create(&mdxpath, None::<&str>).unwrap();
+        let dict = LocalDictionary::load(path).unwrap();
+        let exact = crate::index::LookupOptions { max_distance: Some(0), ..Default::default() };
+        let meanings = |word| dict.lookup(word, &exact).unwrap().into_iter()
+            .map(|(w, m)| (w, m.trim_matches(char::from(0)).to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(meanings("bank"), vec![("bank".to_string(), "river side<hr>financial institution".to_string())]);
+        assert_eq!(meanings("kitty"), vec![("cat".to_string(), "animal".to_string())]);
+        assert_eq!(dict.get_resource("img/cat.png").unwrap().0, b"cat");
+        assert_eq!(dict.get_resource("b.mp3").unwrap().0, b"b");
+    }
+
+    #[test]
+    fn test_link_alias() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "colour\n@@@LINK=color\n</>\ncolor\nhue\n</>\nhues\n@@@LINK=colour\n</>\nmissing\n@@@LINK=nothing\n</>\n";
+        std::fs::write(dir.path().join("link.txt"), content).unwrap();
+        let path = LocalDictionary::create(dir.path().join("link.txt"), None::<&str>).unwrap();
+        let dict = LocalDictionary::load(path).unwrap();
+
+        let exact = crate::index::LookupOptions { max_distance: Some(0), ..Default::default() };
+        for word in ["colour", "hues", "color"] {
+            let words = dict.lookup(word, &exact).unwrap();
+            assert_eq!(words.len(), 1);
+            assert_eq!(words[0].0, "color");
+            assert!(words[0].1.starts_with("hue"));
+        }
+        assert!(dict.lookup("missing", &exact).unwrap().is_empty());
+        let fuzzy = crate::index::LookupOptions { max_distance: Some(2), ..Default::default() };
+        assert_eq!(dict.lookup("colou", &fuzzy).unwrap().len(), 1);
+
+        let mut entries = dict.entries().unwrap().map(|x| x.unwrap()).collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(entries, vec![
+            ("color".to_string(), "hue\0".to_string()),
+            ("colour".to_string(), "@@@LINK=color".to_string()),
+            ("hues".to_string(), "@@@LINK=color".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_search_fulltext() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = concat!(
+            "apple\n<b>苹果</b>, a round fruit\n</>\n",
+            "banana\n<b>香蕉</b>, a long <i>yellow</i> fruit\n</>\n",
+            "pineapple\n<b>菠萝</b>, a tropical fruit that is not an apple\n</>\n",
+        );
+        std::fs::write(dir.path().join("fruit.txt"), content).unwrap();
+        let profile = concat!(
+            "name = \"fruit\"\nversion = \"1.0\"\nauthors = []\ndistributors = []\n",
+            "description = \"\"\nhomepage = \"\"\ncss = \"\"\njs = \"\"\nfulltext = true\n",
+        );
+        std::fs::write(dir.path().join("fruit.toml"), profile).unwrap();
+        let path = LocalDictionary::create(dir.path().join("fruit.txt"), None::<&str>).unwrap();
+        let dict = LocalDictionary::load(path).unwrap();
+        assert!(dict.verify().is_ok());
+
+        let words = |query, limit| {
+            dict.search_fulltext(query, limit).unwrap().into_iter().map(|x| x.word).collect::<Vec<_>>()
+        };
+        assert_eq!(words("香蕉", 10), vec!["banana"]);
+        assert_eq!(words("Fruit", 10), vec!["apple", "banana", "pineapple"]);
+        assert_eq!(words("fruit", 2), vec!["apple", "banana"]);
+        assert_eq!(words("yellow fruit", 10), vec!["banana"]);
+        assert_eq!(words("fruit round", 10), vec!["apple"]);
+        assert!(words("grape", 10).is_empty());
+        let hits = dict.search_fulltext("yellow", 1).unwrap();
+        assert_eq!(hits[0].snippet, "香蕉 , a long yellow fruit");
+
+        let dict = LocalDictionary::load("test/demo.v1.wikit").unwrap();
+        assert!(dict.search_fulltext("89ab", 10).is_err());
+    }
+
+    #[test]
+    fn test_pattern() {
+        use crate::index::PatternSyntax;
+
+        let dir = tempfile::tempdir().unwrap();
+        let words = ["cat", "cats", "cot", "cut", "nation", "station", "tiny"];
+        let content = words.iter().map(|w| format!("{}\n{}\n</>\n", w, w)).collect::<String>();
+        std::fs::write(dir.path().join("pattern.txt"), content).unwrap();
+        let path = LocalDictionary::create(dir.path().join("pattern.txt"), None::<&str>).unwrap();
+        let dict = LocalDictionary::load(path).unwrap();
+
+        let words = |pattern, syntax, limit| {
+            dict.pattern(pattern, syntax, limit).unwrap().into_iter().map(|(w, _)| w).collect::<Vec<_>>()
+        };
+        assert_eq!(words("c?t", PatternSyntax::Glob, 10), vec!["cat", "cot", "cut"]);
+        assert_eq!(words("c[!o]t", PatternSyntax::Glob, 10), vec!["cat", "cut"]);
+        assert_eq!(words("*tion", PatternSyntax::Glob, 10), vec!["nation", "station"]);
+        assert_eq!(words("c?t*", PatternSyntax::Glob, 2), vec!["cat", "cats"]);
+        assert_eq!(words("c[ao]ts?", PatternSyntax::Regex, 10), vec!["cat", "cats", "cot"]);
+        assert_eq!(words("t.*", PatternSyntax::Regex, 10), vec!["tiny"]);
+        assert!(dict.pattern("c(at", PatternSyntax::Regex, 10).is_err());
+        assert_eq!(words("CÀT\\w", PatternSyntax::Regex, 10), vec!["cats"]);
+        assert_eq!(words("\\p{Latin}+T", PatternSyntax::Regex, 10), vec!["cat", "cot", "cut"]);
+        let e = dict.pattern("[ab]*a[ab]{20}", PatternSyntax::Regex, 10).unwrap_err();
+        assert!(e.to_string().contains("too complex"));
+    }
+
+    #[test]
+    fn test_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("inter.txt"), "inter\n1\n</>\ninternational\n2\n</>\ninterpret\n3\n</>\nintro\n4\n</>\n").unwrap();
+        let path = LocalDictionary::create(dir.path().join("inter.txt"), None::<&str>).unwrap();
+        let dict = LocalDictionary::load(path).unwrap();
+
+        let words = |prefix, limit| dict.prefix(prefix, limit).unwrap().into_iter().map(|(w, _)| w).collect::<Vec<_>>();
+        assert_eq!(words("inter", 10), vec!["inter", "international", "interpret"]);
+        assert_eq!(words("inter", 2), vec!["inter", "international"]);
+        assert_eq!(words("int", 10).len(), 4);
+        assert!(words("x", 10).is_empty());
+        let r = dict.prefix("interp", 1).unwrap();
+        assert!(r.len() == 1 && r[0].0 == "interpret" && r[0].1.starts_with("3"));
+    }
+
+    #[test]
+    fn test_load_v1() {
+        let dict = LocalDictionary::load("test/demo.v1.wikit").unwrap();
+        assert_eq!(dict.head.version, 0x01);
+        let r = dict.lookup("b", &Default::default()).unwrap();
+        assert!(r.iter().any(|(w, m)| w == "b" && m.starts_with("cefgh")));
+        assert!(dict.get_resource("audio/a.ogg").is_err());
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("demo.wikit");
+        std::fs::copy("test/demo.v1.wikit", &path).unwrap();
+
+        let outfile = LocalDictionary::upgrade(&path, None::<&str>).unwrap();
+        assert_eq!(outfile, path);
+        let dict = LocalDictionary::load(&path).unwrap();
+        assert_eq!(dict.head.version, LATEST_WIKIT_FMT_VERSION);
+        assert_eq!(dict.head.name, "demo");
+        for (word, meaning) in [("a", "89ab"), ("b", "cefgh"), ("c", "1234567")] {
+            let r = dict.lookup(word, &Default::default()).unwrap();
+            assert!(r.iter().any(|(w, m)| w == word && m.starts_with(meaning)));
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::copy("test/demo.txt", dir.path().join("demo.txt")).unwrap();
+        let path = LocalDictionary::create(dir.path().join("demo.txt"), None::<&str>).unwrap();
+        let dict = LocalDictionary::load(&path).unwrap();
+        assert!(dict.verify().is_ok());
+
+        let mut buf = std::fs::read(&path).unwrap();
+        let offset = dict.head.dbase as usize + 3;
+        buf[offset] = !buf[offset];
+        std::fs::write(&path, &buf).unwrap();
+        match dict.verify() {
+            Err(WikitError::Corrupted { section, offset }) => {
+                assert_eq!(section, "data");
+                assert_eq!(offset, dict.head.dbase);
+            },
+            r => panic!("unexpected verify result: {:?}", r),
+        }
+
+        // section size overflowing its end is broken
+        let sections = [(WikitSection::Data, 0, 1), (WikitSection::Fulltext, 1, u64::MAX)];
+        match check_section_bounds(&sections, 10) {
+            Err(WikitError::Corrupted { section, .. }) => assert_eq!(section, "full-text"),
+            r => panic!("unexpected verify result: {:?}", r),
+        }
+
+        // truncated dictionary fails to load but can be verified
+        std::fs::write(&path, &buf[..dict.head.ibase as usize + 1]).unwrap();
+        assert!(LocalDictionary::load(&path).is_err());
+        match LocalDictionary::verify_file(&path) {
+            Err(WikitError::Corrupted { section, offset }) => {
+                assert_eq!(section, "index");
+                assert_eq!(offset, dict.head.ibase + 1);
+            },
+            r => panic!("unexpected verify result: {:?}", r),
+        }
+    }
+}
@@ -29,6 +29,8 @@ static DICTDB: Lazy<Arc<Mutex<HashMap<String, WikitDictionary>>>> = Lazy::new(||
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 // internal static file server port
 static INTERNAL_FS_PORT: AtomicU16 = AtomicU16::new(7561);
+// number of completions listed for the word being typed
+const PREFIX_LIMIT: usize = 20;
 pub type FFIResult<T> = Result<T, String>;
 
 struct WikitState {
@@ -143,7 +145,12 @@ fn lookup(dictid: String, word: String) -> LookupResponse {
     if let Some(dict) = dictdb.get(&dictid) {
         match dict {
             wikit::WikitDictionary::Local(ld) => {
-                if let Ok(v) = ld.lookup(&word) {
+                if let Ok(v) = ld.lookup(&word, &Default::default()) {
+                    for (k, v) in v {
+                        mp.insert(k, v);
+                    }
+                }
+                if let Ok(v) = ld.prefix(&word, PREFIX_LIMIT) {
                     for (k, v) in v {
                         mp.insert(k, v);
                     }
@@ -152,7 +159,12 @@ fn lookup(dictid: String, word: String) -> LookupResponse {
                 style.push_str(ld.get_style());
             },
             wikit::WikitDictionary::Remote(rd) => {
-                if let Ok(v) = rd.lookup(&word, &dictid) {
+                if let Ok(v) = rd.lookup(&word, &dictid, &Default::default()) {
+                    for (k, v) in v {
+                        mp.insert(k, v);
+                    }
+                }
+                if let Ok(v) = rd.prefix(&word, &dictid, PREFIX_LIMIT) {
                     for (k, v) in v {
                         mp.insert(k, v);
                     }
@@ -160,6 +172,20 @@ fn lookup(dictid: String, word: String) -> LookupResponse {
                 script.push_str(&rd.get_script(&dictid));
                 style.push_str(&rd.get_style(&dictid));
             },
+            wikit::WikitDictionary::Mdx(md) => {
+                if let Ok(v) = md.lookup(&word, &Default::default()) {
+                    for (k, v) in v {
+                        mp.insert(k, v);
+                    }
+                }
+                if let Ok(v) = md.prefix(&word, PREFIX_LIMIT) {
+                    for (k, v) in v {
+                        mp.insert(k, v);
+                    }
+                }
+                script.push_str(md.get_script());
+                style.push_str(md.get_style());
+            },
         }
     }
 
@@ -199,6 +225,11 @@ fn get_dict_list() -> Vec<DictMeta> {
                     dictlist.push(DictMeta { name: ld.head.name.clone(), id: id.clone() });
                     dictdb.insert(id.clone(), dict);
                 },
+                wikit::WikitDictionary::Mdx(ref md) => {
+                    let id = format!("{}", md.path.display());
+                    dictlist.push(DictMeta { name: md.name(), id: id.clone() });
+                    dictdb.insert(id.clone(), dict);
+                },
                 wikit::WikitDictionary::Remote(ref rd) => {
                     if let Ok(ds) = rd.get_dict_list() {
                         for d in ds {
@@ -81,7 +81,7 @@ impl Component for Word {
         let link = ctx.link().clone();
         match msg {
             WordMsg::OnSearchTextChange(_) => {
-                self.input = Field::SearchInput.get().to_lowercase();
+                self.input = Field::SearchInput.get();
                 let epoch = util::get_epoch_millis();
                 if epoch - self.previous_change_epoch >= DEBOUNCE_DELTA && self.input.trim().len() > 0 {
                     link.send_message(WordMsg::StartLookup);
@@ -112,8 +112,15 @@ impl Component for Word {
             WordMsg::OnLookupResult(r) => {
                 let mut fuzzy_list = r.words.keys().map(|v| v.clone()).collect::<Vec<String>>();
                 fuzzy_list.sort();
-                if fuzzy_list.contains(&self.input) {
-                    if let Some(meaning) = r.words.get(&self.input) {
+                // headwords keep their original case such as `Paris`, so prefer the one with
+                // the same case as input
+                let input = self.input.trim();
+                let matched = fuzzy_list.iter()
+                    .find(|w| w.as_str() == input)
+                    .or(fuzzy_list.iter().find(|w| w.to_lowercase() == input.to_lowercase()))
+                    .cloned();
+                if let Some(word) = matched {
+                    if let Some(meaning) = r.words.get(&word) {
                         self.word_meaning = meaning.to_owned();
                         self.show_meaning = true;
                         self.style = r.style.clone();